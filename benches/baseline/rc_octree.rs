@@ -0,0 +1,345 @@
+//! The first octree, with every node behind an `Rc<RefCell<_>>`. Superseded by the arena in
+//! `accum_octree`, and only kept as the baseline `benches/octree.rs` measures it against,
+//! outside the library.
+
+use core::fmt;
+use imgquant::core::accum_octree::get_color_index_rgba;
+use imgquant::core::color_space::{from_linear_light, to_linear_light};
+use imgquant::core::palette_generator::PaletteGenerator;
+use imgquant::core::rgb_helpers::add_colors;
+use std::{cell::RefCell, cmp::{Ordering, Reverse}, collections::BinaryHeap, rc::{Rc, Weak}};
+use image::Rgba;
+
+#[derive(Clone, Debug)]
+pub struct OctreeNode {
+    children: [Option<Rc<RefCell<OctreeNode>>>; 16],
+    color: Rgba<u64>,
+    pixel_count: u32,
+    palette_index: u32,
+}
+
+pub struct LeafOctree {
+    depth: usize,
+    with_alpha: bool,
+    linear_light: bool,
+    root: Rc<RefCell<OctreeNode>>,
+}
+
+/// A node of the tree, flattened so merges can find their parent and siblings.
+struct ReducibleNode {
+    node: Rc<RefCell<OctreeNode>>,
+    parent: Option<usize>,
+    /// Which of the parent's children this is.
+    slot: usize,
+    children: Vec<usize>,
+    unmerged_children: usize,
+    merged: bool,
+    /// Bumped whenever the node's color changes, which reprices its children.
+    version: u32,
+}
+
+/// Merging `order` into its parent, priced against the parent's `version`.
+/// Ordered by cost, ties broken by tree order so the palette doesn't depend on float noise.
+struct MergeCandidate {
+    cost: f64,
+    order: usize,
+    version: u32,
+}
+
+impl PartialEq for MergeCandidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl Eq for MergeCandidate {}
+impl PartialOrd for MergeCandidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for MergeCandidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.cost.total_cmp(&other.cost).then(self.order.cmp(&other.order))
+    }
+}
+
+/// Flattens `node` and everything below it, parents before children. Returns the index of `node`.
+fn collect_reducible(node: &Rc<RefCell<OctreeNode>>, parent: Option<usize>, slot: usize, reducible: &mut Vec<ReducibleNode>) -> usize {
+    let index = reducible.len();
+    reducible.push(ReducibleNode {
+        node: Rc::clone(node),
+        parent,
+        slot,
+        children: Vec::new(),
+        unmerged_children: 0,
+        merged: false,
+        version: 0,
+    });
+    for (child_slot, child) in node.borrow().children.iter().enumerate() {
+        if let Some(child) = child {
+            let child_index = collect_reducible(child, Some(index), child_slot, reducible);
+            reducible[index].children.push(child_index);
+        }
+    }
+    reducible[index].unmerged_children = reducible[index].children.len();
+
+    index
+}
+
+impl LeafOctree {
+    pub fn new(depth: usize) -> Self {
+        Self {
+            root: Rc::new(RefCell::new(OctreeNode::new())),
+            depth,
+            with_alpha: false,
+            linear_light: false,
+        }
+    }
+    /// A hexadecatree: alpha is a fourth axis of the tree, so palette entries carry their own alpha.
+    ///
+    /// Without it, alpha is ignored while building and every palette entry is opaque.
+    pub fn with_alpha(depth: usize) -> Self {
+        Self {
+            with_alpha: true,
+            ..Self::new(depth)
+        }
+    }
+    /// Averages leaves in linear light instead of on the sRGB bytes, so mixed shades
+    /// don't come out too dark. The tree itself is still split on the sRGB bits.
+    pub fn linear_light(mut self, linear_light: bool) -> Self {
+        self.linear_light = linear_light;
+        self
+    }
+
+    /// Merges childless nodes into their parents, cheapest first, ImageMagick style,
+    /// until at most `color_count` nodes still hold a color.
+    ///
+    /// Every merge takes away at most one color, so the palette is exactly
+    /// `min(color_count, leaves)` long, where the leaves are the distinct colors at the
+    /// tree's depth. At least one color is kept for a non-empty tree.
+    ///
+    /// A merge costs the squared error it adds, so a parent that holds no color yet takes
+    /// its first child for free. Parents keep their other children, and their own color
+    /// for whatever doesn't fall into one of them.
+    pub fn make_palette(&mut self, color_count: i32) -> Vec<Rgba<u8>> {
+        let mut palette = Vec::<Rgba<u8>>::new();
+        let mut leaf_count = self.get_leaf_nodes().len() as i32;
+
+        let mut reducible = Vec::<ReducibleNode>::new();
+        collect_reducible(&self.root, None, 0, &mut reducible);
+        let mut queue = BinaryHeap::new();
+        for (order, entry) in reducible.iter().enumerate() {
+            if let Some(parent) = entry.parent && entry.unmerged_children == 0 {
+                let cost = reducible[parent].node.borrow().merge_cost(&entry.node.borrow(), self.with_alpha);
+                queue.push(Reverse(MergeCandidate { cost, order, version: 0 }));
+            }
+        }
+
+        while leaf_count > color_count && let Some(Reverse(candidate)) = queue.pop() {
+            let entry = &reducible[candidate.order];
+            let Some(parent) = entry.parent else { continue };
+            if entry.merged || candidate.version != reducible[parent].version {
+                continue;
+            }
+
+            let mut parent_node = reducible[parent].node.borrow_mut();
+            if parent_node.is_leaf() {
+                leaf_count -= 1;
+            }
+            parent_node.merge_child(entry.slot);
+            drop(parent_node);
+            reducible[candidate.order].merged = true;
+            reducible[parent].unmerged_children -= 1;
+            reducible[parent].version += 1;
+
+            let parent_entry = &reducible[parent];
+            let parent_node = parent_entry.node.borrow();
+            for &sibling in &parent_entry.children {
+                let sibling_entry = &reducible[sibling];
+                if !sibling_entry.merged && sibling_entry.unmerged_children == 0 {
+                    let cost = parent_node.merge_cost(&sibling_entry.node.borrow(), self.with_alpha);
+                    queue.push(Reverse(MergeCandidate { cost, order: sibling, version: parent_entry.version }));
+                }
+            }
+            if parent_entry.unmerged_children == 0 && let Some(grandparent) = parent_entry.parent {
+                let grandparent_entry = &reducible[grandparent];
+                let cost = grandparent_entry.node.borrow().merge_cost(&parent_node, self.with_alpha);
+                queue.push(Reverse(MergeCandidate { cost, order: parent, version: grandparent_entry.version }));
+            }
+        }
+
+        for (palette_index, node) in self.get_leaf_nodes().iter().enumerate() {
+            if let Some(node) = node.upgrade() {
+                let mut borrowed_node = node.borrow_mut();
+                let [r, g, b, a] = borrowed_node.color.0.map(|c| c / u64::from(borrowed_node.pixel_count));
+                let rgb = [r, g, b].map(|c| if self.linear_light { from_linear_light(c as u16) } else { c as u8 });
+                palette.push(Rgba::<u8>([rgb[0], rgb[1], rgb[2], a as u8]));
+                borrowed_node.palette_index = palette_index as u32;
+            }
+        }
+
+        palette
+    }
+
+    /// Alpha is forced to opaque unless the tree was made `with_alpha`.
+    pub fn add_color(&mut self, color: Rgba<u8>) {
+        let color = self.normalize(color);
+        let [r, g, b, a] = color.0;
+        let sample = if self.linear_light {
+            Rgba([to_linear_light(r), to_linear_light(g), to_linear_light(b), u16::from(a)])
+        } else {
+            Rgba(color.0.map(u16::from))
+        };
+        self.root.borrow_mut().add_color(color, &sample, 0, self.depth, self.with_alpha);
+    }
+    /// Returns the palette index for the closest color in the octree to your given color.
+    /// 
+    /// # Arguments
+    /// * `color` - The color value to find the palette index of.
+    /// * `force_find_color` - If `true`, the function will force the octree
+    ///   to find colors. If `false`, it can return a None.
+    ///
+    /// # Returns
+    /// * `Some(index)` if a suitable match is found.
+    /// * `None` if no match is found.
+    pub fn get_palette_index(&self, color: Rgba<u8>, force_find_color: bool) -> Option<usize> {
+        self.root.borrow().get_palette_index(color, 0, force_find_color, self.with_alpha)
+    }
+    /// Every node holding a palette color, the root included once everything is merged into it.
+    pub fn get_leaf_nodes(&self) -> Vec<Weak<RefCell<OctreeNode>>> {
+        let root = self.root.borrow();
+        let mut leaf_nodes = Vec::new();
+        if root.is_leaf() {
+            leaf_nodes.push(Rc::downgrade(&self.root));
+        }
+        leaf_nodes.extend(root.get_leaf_nodes());
+
+        leaf_nodes
+    }
+}
+
+impl PaletteGenerator for LeafOctree {
+    fn add_color(&mut self, color: Rgba<u8>) {
+        LeafOctree::add_color(self, color)
+    }
+    fn make_palette(&mut self, color_count: i32) -> Vec<Rgba<u8>> {
+        LeafOctree::make_palette(self, color_count)
+    }
+    fn get_palette_index(&self, color: Rgba<u8>, force_find_color: bool) -> Option<usize> {
+        LeafOctree::get_palette_index(self, color, force_find_color)
+    }
+    fn color_count(&self) -> usize {
+        self.get_leaf_nodes().len()
+    }
+    fn has_alpha(&self) -> bool {
+        self.with_alpha
+    }
+}
+
+impl fmt::Display for LeafOctree {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // i will implement this in bevy soon.
+        let node = self.root.borrow();
+        write!(f, "{}", node)
+    }
+}
+
+impl Default for OctreeNode {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OctreeNode {
+    pub fn new() -> Self {
+        Self {
+            color: Rgba([0, 0, 0, 0]),
+            pixel_count: 0,
+            palette_index: 0,
+            children: std::array::from_fn(|_| None),
+        }
+    }
+    /// `color` picks the path down the tree, `sample` is what the leaf accumulates.
+    pub fn add_color(&mut self, color: Rgba<u8>, sample: &Rgba<u16>, level: usize, depth: usize, with_alpha: bool) {
+        if level >= depth {
+            add_colors(&mut self.color, sample);
+            self.pixel_count += 1;
+            return;
+        }
+        let index = get_color_index_rgba(color, level, with_alpha);
+        let child = self.children[index].get_or_insert_with(|| Rc::new(RefCell::new(OctreeNode::new())));
+        child.borrow_mut().add_color(color, sample, level + 1, depth, with_alpha);
+    }
+    pub fn get_palette_index(&self, color: Rgba<u8>, level: usize, force_find_color: bool, with_alpha: bool) -> Option<usize> {
+        // nodes at the bottom of the tree have no bit left to index children with.
+        let has_children = self.children.iter().any(Option::is_some);
+        if has_children && let Some(cell) = &self.children[get_color_index_rgba(color, level, with_alpha)] {
+            let c = cell.borrow();
+            return c.get_palette_index(color, level + 1, force_find_color, with_alpha);
+        }
+        if self.is_leaf() {
+            return Some(self.palette_index as usize);
+        }
+        if force_find_color && let Some(node) = self.children.iter().flatten().next() {
+            let c = node.borrow();
+            return c.get_palette_index(color, level + 1, force_find_color, with_alpha);
+        }
+        None
+    }
+    /// Leaves below this node. Leaves can have children of their own after merging, so this looks under them too.
+    pub fn get_leaf_nodes(&self) -> Vec<Weak<RefCell<OctreeNode>>> {
+        let mut leaf_nodes = Vec::<Weak<RefCell<OctreeNode>>>::new();
+        for child in self.children.iter().flatten() {
+            let borrowed_child = child.borrow();
+            if borrowed_child.is_leaf() {
+                leaf_nodes.push(Rc::downgrade(child)); // reference counted.
+            }
+            for element in borrowed_child.get_leaf_nodes() {
+                leaf_nodes.push(element);
+            };
+        }
+
+        leaf_nodes
+    }
+    /// Folds the childless child in `slot` into this node.
+    pub fn merge_child(&mut self, slot: usize) {
+        if let Some(child) = self.children[slot].take() {
+            let borrowed_child = child.borrow();
+            self.pixel_count += borrowed_child.pixel_count;
+            add_colors(&mut self.color, &borrowed_child.color);
+        }
+    }
+    /// Squared error added by merging `child` into this node, `n_c * n_p / (n_c + n_p) * |mean_c - mean_p|^2`.
+    /// Free if this node holds no color yet.
+    pub fn merge_cost(&self, child: &OctreeNode, with_alpha: bool) -> f64 {
+        if !self.is_leaf() {
+            return 0.0;
+        }
+        let channel_count = if with_alpha { 4 } else { 3 };
+        let own_count = self.pixel_count as f64;
+        let child_count = child.pixel_count as f64;
+        let distance: f64 = (0..channel_count)
+            .map(|c| (child.color.0[c] as f64 / child_count - self.color.0[c] as f64 / own_count).powi(2))
+            .sum();
+
+        child_count * own_count / (child_count + own_count) * distance
+    }
+
+    /// Whether this node holds a palette color.
+    pub fn is_leaf(&self) -> bool {
+        self.pixel_count > 0
+    }
+}
+
+impl fmt::Display for OctreeNode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let [r, g, b, _] = self.color.0.map(|x| x as u8);
+        let children: Vec<String> = self.children.iter()
+            .filter_map(|c| c.as_ref().map(|x| format!("{}", x.borrow())))
+            .collect();
+        write!(f, "<color: #{:02X}{:02X}{:02X}, pixel_count: {}, children: [{}]>", r, g, b, self.pixel_count, children.join(", "))
+    }
+}
+
+
+
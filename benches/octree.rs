@@ -0,0 +1,92 @@
+//! The arena octree against the `Rc<RefCell<_>>` one it replaced, and the Morton octree,
+//! on every source image in `images/`.
+//!
+//! `cargo bench --bench octree`, or `cargo bench --bench octree -- Kaguya` for a single image.
+
+use std::fs;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use image::RgbaImage;
+use imgquant::core::accum_octree;
+use imgquant::core::palette_generator::PaletteGenerator;
+use imgquant::morton::octree::MortonOctree;
+
+#[allow(dead_code)]
+#[path = "baseline/rc_octree.rs"]
+mod rc_octree;
+
+const DEPTH: usize = 6;
+const COLORS: i32 = 256;
+
+/// The images that aren't the output of some earlier run.
+fn source_images() -> Vec<(String, RgbaImage)> {
+    let mut paths: Vec<_> = fs::read_dir("images")
+        .expect("run from the crate root")
+        .map(|entry| entry.expect("readable images directory").path())
+        .filter(|path| {
+            let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+            !["_quant_dither", "_line_filter", "_processed"].iter().any(|suffix| stem.contains(suffix))
+        })
+        .collect();
+    paths.sort();
+    paths.into_iter()
+        .map(|path| {
+            let name = path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+            (name, image::open(&path).expect("decodable image").to_rgba8())
+        })
+        .collect()
+}
+
+fn build_arena(image: &RgbaImage) -> accum_octree::LeafOctree {
+    let mut tree = accum_octree::LeafOctree::new(DEPTH);
+    image.pixels().for_each(|rgba| tree.add_color(*rgba));
+    tree
+}
+
+fn build_rc(image: &RgbaImage) -> rc_octree::LeafOctree {
+    let mut tree = rc_octree::LeafOctree::new(DEPTH);
+    image.pixels().for_each(|rgba| tree.add_color(*rgba));
+    tree
+}
+
+fn build_morton(image: &RgbaImage) -> MortonOctree {
+    let mut tree = MortonOctree::new(DEPTH);
+    image.pixels().for_each(|rgba| tree.add_color(*rgba));
+    tree
+}
+
+fn octree(c: &mut Criterion) {
+    for (name, image) in source_images() {
+        let mut group = c.benchmark_group(format!("octree/{name}"));
+        group.sample_size(10);
+
+        group.bench_function(BenchmarkId::new("build", "arena"), |b| b.iter(|| build_arena(&image)));
+        group.bench_function(BenchmarkId::new("build", "rc"), |b| b.iter(|| build_rc(&image)));
+        group.bench_function(BenchmarkId::new("build", "morton"), |b| b.iter(|| build_morton(&image)));
+
+        group.bench_function(BenchmarkId::new("palette", "arena"), |b| b.iter(|| build_arena(&image).make_palette(COLORS)));
+        group.bench_function(BenchmarkId::new("palette", "rc"), |b| b.iter(|| build_rc(&image).make_palette(COLORS)));
+        group.bench_function(BenchmarkId::new("palette", "morton"), |b| b.iter(|| build_morton(&image).make_palette(COLORS)));
+
+        let mut arena = build_arena(&image);
+        arena.make_palette(COLORS);
+        let mut rc = build_rc(&image);
+        rc.make_palette(COLORS);
+        let mut morton = build_morton(&image);
+        morton.make_palette(COLORS);
+        group.bench_function(BenchmarkId::new("lookup", "arena"), |b| {
+            b.iter(|| image.pixels().map(|rgba| arena.get_palette_index(*rgba, true).unwrap_or(0)).sum::<usize>())
+        });
+        group.bench_function(BenchmarkId::new("lookup", "rc"), |b| {
+            b.iter(|| image.pixels().map(|rgba| rc.get_palette_index(*rgba, true).unwrap_or(0)).sum::<usize>())
+        });
+        group.bench_function(BenchmarkId::new("lookup", "morton"), |b| {
+            b.iter(|| image.pixels().map(|rgba| morton.get_palette_index(*rgba, true).unwrap_or(0)).sum::<usize>())
+        });
+
+        group.finish();
+    }
+}
+
+criterion_group!(benches, octree);
+criterion_main!(benches);
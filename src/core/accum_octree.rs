@@ -1,9 +1,11 @@
 
 use core::fmt;
+use crate::core::color_space::{from_linear_light, to_linear_light};
+use crate::core::palette_generator::PaletteGenerator;
 use crate::core::rgb_helpers::add_colors;
-use std::{cell::RefCell, rc::{Rc, Weak}};
-use image::Rgb;
-// note: 0, 1, 2 corresponds to R, G, B
+use std::{cmp::{Ordering, Reverse}, collections::BinaryHeap};
+use image::{Rgb, Rgba};
+// note: 0, 1, 2 corresponds to R, G, B (and 3 to A in the hexadecatree)
 
 pub fn get_color_index(color: Rgb<u8>, level: usize) -> usize {
     let [r, g, b] = color.0;
@@ -13,213 +15,407 @@ pub fn get_color_index(color: Rgb<u8>, level: usize) -> usize {
     if g & mask != 0 { index |= 0b010; }
     if b & mask != 0 { index |= 0b001; }
 
-    return index;
+    index
 }
 
+/// Same as `get_color_index`, with the alpha bit on top when `with_alpha` is set,
+/// turning each node into one of 16 children instead of 8.
+pub fn get_color_index_rgba(color: Rgba<u8>, level: usize, with_alpha: bool) -> usize {
+    let [r, g, b, a] = color.0;
+    let mut index = get_color_index(Rgb([r, g, b]), level);
+    let mask = 0b10000000 >> level;
+    if with_alpha && a & mask != 0 { index |= 0b1000; }
+
+    index
+}
+
+/// The root is always the first node of the arena.
+const ROOT: u32 = 0;
+/// The root is never anyone's child, so its index marks an empty child slot.
+const NO_CHILD: u32 = ROOT;
+
+/// A node of `LeafOctree`. Children are indices into the tree's node arena.
 #[derive(Clone, Debug)]
 pub struct OctreeNode {
-    children: [Option<Rc<RefCell<OctreeNode>>>; 8],
-    color: Rgb<u32>,
+    children: [u32; 16],
+    color: Rgba<u64>,
     pixel_count: u32,
     palette_index: u32,
 }
 
-type LevelVec = Vec<Vec<Weak<RefCell<OctreeNode>>>>;
+/// Every node lives in one `Vec`, so building and lookups never allocate per node
+/// or chase reference counts. Merged nodes are unlinked from their parent and left in place.
 pub struct LeafOctree {
     depth: usize,
-    root: OctreeNode,
-    levels: LevelVec,
+    with_alpha: bool,
+    linear_light: bool,
+    nodes: Vec<OctreeNode>,
+}
+
+/// A node of the tree, flattened so merges can find their parent and siblings.
+struct ReducibleNode {
+    node: u32,
+    parent: Option<usize>,
+    /// Which of the parent's children this is.
+    slot: usize,
+    children: Vec<usize>,
+    unmerged_children: usize,
+    merged: bool,
+    /// Bumped whenever the node's color changes, which reprices its children.
+    version: u32,
+}
+
+/// Merging `order` into its parent, priced against the parent's `version`.
+/// Ordered by cost, ties broken by tree order so the palette doesn't depend on float noise.
+struct MergeCandidate {
+    cost: f64,
+    order: usize,
+    version: u32,
+}
+
+impl PartialEq for MergeCandidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl Eq for MergeCandidate {}
+impl PartialOrd for MergeCandidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for MergeCandidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.cost.total_cmp(&other.cost).then(self.order.cmp(&other.order))
+    }
 }
 
 impl LeafOctree {
     pub fn new(depth: usize) -> Self {
-        let mut nodes = Vec::new();
-        nodes.reserve(10_000); // over-allocating at higher levels (level 1 to 3~)
         Self {
-            root: OctreeNode::new(),
-            levels: vec![nodes; depth + 1],
+            nodes: vec![OctreeNode::new()],
             depth,
+            with_alpha: false,
+            linear_light: false,
+        }
+    }
+    /// A hexadecatree: alpha is a fourth axis of the tree, so palette entries carry their own alpha.
+    ///
+    /// Without it, alpha is ignored while building and every palette entry is opaque.
+    pub fn with_alpha(depth: usize) -> Self {
+        Self {
+            with_alpha: true,
+            ..Self::new(depth)
         }
     }
+    /// Averages leaves in linear light instead of on the sRGB bytes, so mixed shades
+    /// don't come out too dark. The tree itself is still split on the sRGB bits.
+    pub fn linear_light(mut self, linear_light: bool) -> Self {
+        self.linear_light = linear_light;
+        self
+    }
 
-    pub fn make_palette(&mut self, color_count: i32) -> Vec<Rgb<u8>> {
-        let mut palette = Vec::<Rgb<u8>>::new();
-        let mut palette_index = 0;
-        let leaves = self.get_leaf_nodes();
-        let mut leaf_count = leaves.len() as i32;
+    fn node(&self, index: u32) -> &OctreeNode {
+        &self.nodes[index as usize]
+    }
 
-        for level_index in (0..(self.depth as usize - 1)).rev() {
-            let level = &mut self.levels[level_index];
-            for node in level {
-                if let Some(node) = node.upgrade() {
-                    let mut node = node.borrow_mut();
-                    leaf_count -= node.remove_leaves();
-                }
-                if leaf_count <= color_count {
-                    break;
-                }
-            }
-            if leaf_count <= color_count {
-                break;
-            }
+    /// Flattens `node` and everything below it, parents before children. Returns the index of `node`.
+    fn collect_reducible(&self, node: u32, parent: Option<usize>, slot: usize, reducible: &mut Vec<ReducibleNode>) -> usize {
+        let index = reducible.len();
+        reducible.push(ReducibleNode {
+            node,
+            parent,
+            slot,
+            children: Vec::new(),
+            unmerged_children: 0,
+            merged: false,
+            version: 0,
+        });
+        for (child_slot, child) in self.node(node).children() {
+            let child_index = self.collect_reducible(child, Some(index), child_slot, reducible);
+            reducible[index].children.push(child_index);
+        }
+        reducible[index].unmerged_children = reducible[index].children.len();
+
+        index
+    }
+
+    /// Folds the childless child in `slot` of `parent` into it.
+    fn merge_child(&mut self, parent: u32, slot: usize) {
+        let child = std::mem::replace(&mut self.nodes[parent as usize].children[slot], NO_CHILD);
+        if child == NO_CHILD {
+            return;
         }
+        let OctreeNode { color, pixel_count, .. } = self.nodes[child as usize];
+        let parent = &mut self.nodes[parent as usize];
+        parent.pixel_count += pixel_count;
+        add_colors(&mut parent.color, &color);
+    }
+
+    fn merge_cost(&self, parent: u32, child: u32) -> f64 {
+        self.node(parent).merge_cost(self.node(child), self.with_alpha)
+    }
+
+    /// Merges childless nodes into their parents, cheapest first, ImageMagick style,
+    /// until at most `color_count` nodes still hold a color.
+    ///
+    /// Every merge takes away at most one color, so the palette is exactly
+    /// `min(color_count, leaves)` long, where the leaves are the distinct colors at the
+    /// tree's depth. At least one color is kept for a non-empty tree.
+    ///
+    /// A merge costs the squared error it adds, so a parent that holds no color yet takes
+    /// its first child for free. Parents keep their other children, and their own color
+    /// for whatever doesn't fall into one of them.
+    pub fn make_palette(&mut self, color_count: i32) -> Vec<Rgba<u8>> {
+        let mut palette = Vec::<Rgba<u8>>::new();
+        let mut leaf_count = self.leaf_indices().len() as i32;
 
-        for ele in self.levels.iter_mut() {
-            ele.clear();
+        let mut reducible = Vec::<ReducibleNode>::new();
+        self.collect_reducible(ROOT, None, 0, &mut reducible);
+        let mut queue = BinaryHeap::new();
+        for (order, entry) in reducible.iter().enumerate() {
+            if let Some(parent) = entry.parent && entry.unmerged_children == 0 {
+                let cost = self.merge_cost(reducible[parent].node, entry.node);
+                queue.push(Reverse(MergeCandidate { cost, order, version: 0 }));
+            }
         }
 
-        let mut leaves = self.get_leaf_nodes();
-        for node in leaves.iter_mut() {
-            if palette_index >= color_count {
-                break;
+        while leaf_count > color_count && let Some(Reverse(candidate)) = queue.pop() {
+            let entry = &reducible[candidate.order];
+            let Some(parent) = entry.parent else { continue };
+            if entry.merged || candidate.version != reducible[parent].version {
+                continue;
+            }
+
+            let parent_node = reducible[parent].node;
+            if self.node(parent_node).is_leaf() {
+                leaf_count -= 1;
             }
-            if let Some(node) = node.upgrade() {
-                let mut borrowed_node = node.borrow_mut();
-                if borrowed_node.is_leaf() {
-                    let [r, g, b] = borrowed_node.color.0;
-                    let rgb = Rgb::<u8>([
-                        (r / borrowed_node.pixel_count) as u8,
-                        (g / borrowed_node.pixel_count) as u8,
-                        (b / borrowed_node.pixel_count) as u8,
-                    ]);
-                    palette.push(rgb);
+            self.merge_child(parent_node, entry.slot);
+            reducible[candidate.order].merged = true;
+            reducible[parent].unmerged_children -= 1;
+            reducible[parent].version += 1;
+
+            let parent_entry = &reducible[parent];
+            for &sibling in &parent_entry.children {
+                let sibling_entry = &reducible[sibling];
+                if !sibling_entry.merged && sibling_entry.unmerged_children == 0 {
+                    let cost = self.merge_cost(parent_node, sibling_entry.node);
+                    queue.push(Reverse(MergeCandidate { cost, order: sibling, version: parent_entry.version }));
                 }
-                borrowed_node.palette_index = palette_index as u32;
-                palette_index += 1;
             }
+            if parent_entry.unmerged_children == 0 && let Some(grandparent) = parent_entry.parent {
+                let grandparent_entry = &reducible[grandparent];
+                let cost = self.merge_cost(grandparent_entry.node, parent_node);
+                queue.push(Reverse(MergeCandidate { cost, order: parent, version: grandparent_entry.version }));
+            }
+        }
+
+        for (palette_index, leaf) in self.leaf_indices().into_iter().enumerate() {
+            let node = &mut self.nodes[leaf as usize];
+            let [r, g, b, a] = node.color.0.map(|c| c / u64::from(node.pixel_count));
+            let rgb = [r, g, b].map(|c| if self.linear_light { from_linear_light(c as u16) } else { c as u8 });
+            palette.push(Rgba::<u8>([rgb[0], rgb[1], rgb[2], a as u8]));
+            node.palette_index = palette_index as u32;
         }
 
         palette
     }
 
-    pub fn add_color(&mut self, color: Rgb<u8>) {
-        self.root.add_color(color, 0, &mut self.levels, self.depth);
+    /// Alpha is forced to opaque unless the tree was made `with_alpha`.
+    pub fn add_color(&mut self, color: Rgba<u8>) {
+        let color = self.normalize(color);
+        let [r, g, b, a] = color.0;
+        let sample = if self.linear_light {
+            Rgba([to_linear_light(r), to_linear_light(g), to_linear_light(b), u16::from(a)])
+        } else {
+            Rgba(color.0.map(u16::from))
+        };
+
+        let mut node = ROOT;
+        for level in 0..self.depth {
+            let slot = get_color_index_rgba(color, level, self.with_alpha);
+            node = match self.node(node).children[slot] {
+                NO_CHILD => {
+                    let child = self.nodes.len() as u32;
+                    self.nodes.push(OctreeNode::new());
+                    self.nodes[node as usize].children[slot] = child;
+                    child
+                },
+                child => child,
+            };
+        }
+        let leaf = &mut self.nodes[node as usize];
+        add_colors(&mut leaf.color, &sample);
+        leaf.pixel_count += 1;
+    }
+    /// Adds every pixel of `other` into this tree, as if they had been added here.
+    ///
+    /// Counts and color sums are integers, so the merged tree is the same no matter how
+    /// the pixels were split up or in which order trees are merged. Both trees need the same
+    /// depth and settings, and shouldn't have made a palette yet.
+    pub fn merge(&mut self, other: &LeafOctree) {
+        assert!(
+            self.depth == other.depth && self.with_alpha == other.with_alpha && self.linear_light == other.linear_light,
+            "only octrees built the same way can be merged"
+        );
+        let mut stack = vec![(ROOT, ROOT)];
+        while let Some((from, into)) = stack.pop() {
+            let OctreeNode { color, pixel_count, .. } = *other.node(from);
+            let node = &mut self.nodes[into as usize];
+            node.pixel_count += pixel_count;
+            add_colors(&mut node.color, &color);
+            for (slot, child) in other.node(from).children() {
+                let target = match self.node(into).children[slot] {
+                    NO_CHILD => {
+                        let target = self.nodes.len() as u32;
+                        self.nodes.push(OctreeNode::new());
+                        self.nodes[into as usize].children[slot] = target;
+                        target
+                    },
+                    target => target,
+                };
+                stack.push((child, target));
+            }
+        }
     }
     /// Returns the palette index for the closest color in the octree to your given color.
-    /// 
+    ///
     /// # Arguments
     /// * `color` - The color value to find the palette index of.
     /// * `force_find_color` - If `true`, the function will force the octree
-    /// to find colors. If `false`, it can return a None.
+    ///   to find colors. If `false`, it can return a None.
     ///
     /// # Returns
     /// * `Some(index)` if a suitable match is found.
     /// * `None` if no match is found.
-    pub fn get_palette_index(&self, color: Rgb<u8>, force_find_color: bool) -> Option<usize> {
-        self.root.get_palette_index(color, 0, force_find_color)
+    pub fn get_palette_index(&self, color: Rgba<u8>, force_find_color: bool) -> Option<usize> {
+        let mut node = self.node(ROOT);
+        let mut level = 0;
+        loop {
+            // nodes at the bottom of the tree have no bit left to index children with.
+            let first_child = node.children().next();
+            let child = match first_child {
+                Some(_) => node.children[get_color_index_rgba(color, level, self.with_alpha)],
+                None => NO_CHILD,
+            };
+            if child != NO_CHILD {
+                node = self.node(child);
+            } else if node.is_leaf() {
+                return Some(node.palette_index as usize);
+            } else if force_find_color && let Some((_, child)) = first_child {
+                node = self.node(child);
+            } else {
+                return None;
+            }
+            level += 1;
+        }
+    }
+    /// Every node holding a palette color, the root included once everything is merged into it.
+    /// Nodes come in palette order.
+    pub fn get_leaf_nodes(&self) -> Vec<&OctreeNode> {
+        self.leaf_indices().into_iter().map(|leaf| self.node(leaf)).collect()
+    }
+
+    // leaves can have children of their own after merging, so this looks under them too.
+    fn leaf_indices(&self) -> Vec<u32> {
+        let mut leaves = Vec::new();
+        let mut stack = vec![ROOT];
+        while let Some(index) = stack.pop() {
+            let node = self.node(index);
+            if node.is_leaf() {
+                leaves.push(index);
+            }
+            stack.extend(node.children().map(|(_, child)| child).rev());
+        }
+
+        leaves
     }
-    pub fn get_leaf_nodes(&self) -> Vec<Weak<RefCell<OctreeNode>>> {
-        let leaves = self.root.get_leaf_nodes();
-        return leaves;
+
+    fn fmt_node(&self, index: u32, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let node = self.node(index);
+        let [r, g, b, _] = node.color.0.map(|x| x as u8);
+        write!(f, "<color: #{:02X}{:02X}{:02X}, pixel_count: {}, children: [", r, g, b, node.pixel_count)?;
+        for (i, (_, child)) in node.children().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            self.fmt_node(child, f)?;
+        }
+        write!(f, "]>")
+    }
+}
+
+impl PaletteGenerator for LeafOctree {
+    fn add_color(&mut self, color: Rgba<u8>) {
+        LeafOctree::add_color(self, color)
+    }
+    fn make_palette(&mut self, color_count: i32) -> Vec<Rgba<u8>> {
+        LeafOctree::make_palette(self, color_count)
+    }
+    fn get_palette_index(&self, color: Rgba<u8>, force_find_color: bool) -> Option<usize> {
+        LeafOctree::get_palette_index(self, color, force_find_color)
+    }
+    fn color_count(&self) -> usize {
+        self.leaf_indices().len()
+    }
+    fn has_alpha(&self) -> bool {
+        self.with_alpha
     }
 }
 
 impl fmt::Display for LeafOctree {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         // i will implement this in bevy soon.
-        let node = &self.root;
-        write!(f, "{}", node.to_string())
+        self.fmt_node(ROOT, f)
+    }
+}
+
+impl Default for OctreeNode {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 impl OctreeNode {
     pub fn new() -> Self {
         Self {
-            color: Rgb([0, 0, 0]),
+            color: Rgba([0, 0, 0, 0]),
             pixel_count: 0,
             palette_index: 0,
-            children: std::array::from_fn(|_| None),
-        }
-    }
-    pub fn add_color(&mut self, color: Rgb<u8>, level: usize, levels: &mut LevelVec, depth: usize) {
-        if level >= depth {
-            add_colors(&mut self.color, &color);
-            self.pixel_count += 1;
-            return;
-        }
-        let index = get_color_index(color, level);
-        let child = &mut self.children[index];
-        if child.is_none() {
-            let node = Rc::new(RefCell::new(OctreeNode::new()));
-            levels[level].push(Rc::downgrade(&node));
-            self.children[index] = Some(node);
-        }
-        let mut node = self.children[index].as_ref().unwrap().borrow_mut();
-        node.add_color(color, level + 1, levels, depth);
-    }
-    pub fn get_palette_index(&self, color: Rgb<u8>, level: usize, force_find_color: bool) -> Option<usize> {
-        if self.is_leaf() {
-            return Some(self.palette_index as usize);
-        } else {
-            let index = get_color_index(color, level);
-            match &self.children[index] {
-                Some(cell) => {
-                    let c = cell.borrow();
-                    return c.get_palette_index(color, level + 1, force_find_color);
-                },
-                None => {
-                    if force_find_color {
-                        for node in self.children.iter() {
-                            if let Some(node) = node {
-                                let c = node.borrow();
-                                return c.get_palette_index(color, level + 1, force_find_color);
-                            }
-                        }
-                    }
-                    None
-                },
-            }
+            children: [NO_CHILD; 16],
         }
     }
-    pub fn get_leaf_nodes(&self) -> Vec<Weak<RefCell<OctreeNode>>> {
-        let mut leaf_nodes = Vec::<Weak<RefCell<OctreeNode>>>::new();
-        for child in self.children.iter() {
-            if let Some(child) = child {
-                let borrowed_child = child.borrow();
-                if borrowed_child.is_leaf() {
-                    leaf_nodes.push(Rc::downgrade(child)); // reference counted.
-                } else {
-                    for element in borrowed_child.get_leaf_nodes() {
-                        leaf_nodes.push(element);
-                    };
-                }
-            }
-        }
-
-        leaf_nodes
+    /// The occupied child slots, with the arena index of the child in each.
+    fn children(&self) -> impl DoubleEndedIterator<Item = (usize, u32)> + '_ {
+        self.children.iter().copied().enumerate().filter(|&(_, child)| child != NO_CHILD)
     }
-    pub fn remove_leaves(&mut self) -> i32 {
-        let mut leaves_removed = 0;
-    
-        for (_i, child) in self.children.iter_mut().enumerate() {
-            if let Some(child) = child {
-                let borrowed_child = child.borrow();
-                if borrowed_child.is_leaf() {
-                    leaves_removed += 1;
-                }
-                self.pixel_count += borrowed_child.pixel_count;
-                add_colors(&mut self.color, &borrowed_child.color);
-            }
-            *child = None;
+    /// Squared error added by merging `child` into this node, `n_c * n_p / (n_c + n_p) * |mean_c - mean_p|^2`.
+    /// Free if this node holds no color yet.
+    pub fn merge_cost(&self, child: &OctreeNode, with_alpha: bool) -> f64 {
+        if !self.is_leaf() {
+            return 0.0;
         }
+        let channel_count = if with_alpha { 4 } else { 3 };
+        let own_count = self.pixel_count as f64;
+        let child_count = child.pixel_count as f64;
+        let distance: f64 = (0..channel_count)
+            .map(|c| (child.color.0[c] as f64 / child_count - self.color.0[c] as f64 / own_count).powi(2))
+            .sum();
 
-        leaves_removed - 1
+        child_count * own_count / (child_count + own_count) * distance
     }
 
+    /// Whether this node holds a palette color.
     pub fn is_leaf(&self) -> bool {
-        return self.pixel_count > 0;
+        self.pixel_count > 0
     }
-}
-
-impl fmt::Display for OctreeNode {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let [r, g, b] = self.color.0.map(|x| x as u8);
-        let children: Vec<String> = self.children.iter()
-            .filter_map(|c| c.as_ref().map(|x| format!("{}", x.borrow())))
-            .collect();
-        write!(f, "<color: #{:02X}{:02X}{:02X}, pixel_count: {}, children: [{}]>", r, g, b, self.pixel_count, children.join(", "))
+    /// How many pixels were added to this node, or merged into it.
+    pub fn pixel_count(&self) -> u32 {
+        self.pixel_count
+    }
+    /// This node's palette entry, once `make_palette` has run.
+    pub fn palette_index(&self) -> u32 {
+        self.palette_index
     }
 }
-
-
-
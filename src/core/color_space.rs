@@ -0,0 +1,215 @@
+use std::sync::LazyLock;
+use image::Rgba;
+
+use crate::core::rgb_helpers::color_diff_rgba;
+
+/// The space colors are accumulated and compared in.
+///
+/// Every space is packed back into 8 bits per channel, so palette generators and dithering
+/// work on it unchanged. Alpha is never converted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColorSpace {
+    /// Gamma-encoded sRGB bytes, exactly as stored in the image.
+    #[default]
+    Srgb,
+    /// Linear-light RGB. Only 8 bits, so the darkest shades share a handful of values.
+    Linear,
+    /// CIELAB (D65). `L` is scaled to 0..=255, `a` and `b` are offset by 128.
+    Lab,
+    /// Björn Ottosson's OKLab, all three channels scaled by 255, `a` and `b` offset by 128.
+    Oklab,
+    /// Full range BT.601 YCbCr, as in JPEG.
+    Yuv,
+}
+
+// D65 white point.
+const WHITE: [f32; 3] = [0.950_47, 1.0, 1.088_83];
+const LAB_EPSILON: f32 = 216.0 / 24389.0;
+const LAB_KAPPA: f32 = 24389.0 / 27.0;
+
+static SRGB_TO_LINEAR: LazyLock<[f32; 256]> = LazyLock::new(|| {
+    std::array::from_fn(|i| {
+        let c = i as f32 / 255.0;
+        if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+    })
+});
+
+/// Largest value of the 12-bit linear light scale used for averaging and error diffusion.
+pub const LINEAR_LIGHT_MAX: u16 = 4095;
+
+static SRGB_TO_LINEAR_LIGHT: LazyLock<[u16; 256]> = LazyLock::new(|| {
+    SRGB_TO_LINEAR.map(|c| (c * f32::from(LINEAR_LIGHT_MAX)).round() as u16)
+});
+static LINEAR_LIGHT_TO_SRGB: LazyLock<Vec<u8>> = LazyLock::new(|| {
+    (0..=LINEAR_LIGHT_MAX).map(|c| linear_to_srgb(f32::from(c) / f32::from(LINEAR_LIGHT_MAX))).collect()
+});
+
+/// Decodes an sRGB channel to 12-bit linear light, through a lookup table.
+pub fn to_linear_light(c: u8) -> u16 {
+    SRGB_TO_LINEAR_LIGHT[c as usize]
+}
+
+/// Encodes a 12-bit linear light channel back to sRGB, through a lookup table.
+/// Values past `LINEAR_LIGHT_MAX` are clamped.
+pub fn from_linear_light(c: u16) -> u8 {
+    LINEAR_LIGHT_TO_SRGB[c.min(LINEAR_LIGHT_MAX) as usize]
+}
+
+fn linear_to_srgb(c: f32) -> u8 {
+    let c = c.clamp(0.0, 1.0);
+    let c = if c <= 0.003_130_8 { c * 12.92 } else { 1.055 * c.powf(1.0 / 2.4) - 0.055 };
+    (c * 255.0).round() as u8
+}
+
+fn to_byte(c: f32) -> u8 {
+    c.round().clamp(0.0, 255.0) as u8
+}
+
+fn linear_rgb(rgba: Rgba<u8>) -> [f32; 3] {
+    let [r, g, b, _] = rgba.0;
+    [r, g, b].map(|c| SRGB_TO_LINEAR[c as usize])
+}
+
+fn lab_from_linear([r, g, b]: [f32; 3]) -> [f32; 3] {
+    let xyz = [
+        0.412_456_4 * r + 0.357_576_1 * g + 0.180_437_5 * b,
+        0.212_672_9 * r + 0.715_152_2 * g + 0.072_175 * b,
+        0.019_333_9 * r + 0.119_192 * g + 0.950_304_1 * b,
+    ];
+    let [fx, fy, fz] = [0, 1, 2].map(|i| {
+        let t = xyz[i] / WHITE[i];
+        if t > LAB_EPSILON { t.cbrt() } else { (LAB_KAPPA * t + 16.0) / 116.0 }
+    });
+
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+fn linear_from_lab([l, a, b]: [f32; 3]) -> [f32; 3] {
+    let fy = (l + 16.0) / 116.0;
+    let f = [fy + a / 500.0, fy, fy - b / 200.0];
+    let [x, y, z] = [0, 1, 2].map(|i| {
+        let t = f[i] * f[i] * f[i];
+        let t = if t > LAB_EPSILON { t } else { (116.0 * f[i] - 16.0) / LAB_KAPPA };
+        t * WHITE[i]
+    });
+
+    [
+        3.240_454_2 * x - 1.537_138_5 * y - 0.498_531_4 * z,
+        -0.969_266 * x + 1.876_010_8 * y + 0.041_556 * z,
+        0.055_643_4 * x - 0.204_025_9 * y + 1.057_225_2 * z,
+    ]
+}
+
+fn oklab_from_linear([r, g, b]: [f32; 3]) -> [f32; 3] {
+    let l = (0.412_221_47 * r + 0.536_332_55 * g + 0.051_445_995 * b).cbrt();
+    let m = (0.211_903_5 * r + 0.680_699_5 * g + 0.107_396_96 * b).cbrt();
+    let s = (0.088_302_46 * r + 0.281_718_85 * g + 0.629_978_7 * b).cbrt();
+
+    [
+        0.210_454_26 * l + 0.793_617_8 * m - 0.004_072_047 * s,
+        1.977_998_5 * l - 2.428_592_2 * m + 0.450_593_7 * s,
+        0.025_904_037 * l + 0.782_771_77 * m - 0.808_675_77 * s,
+    ]
+}
+
+fn linear_from_oklab([l, a, b]: [f32; 3]) -> [f32; 3] {
+    let l_ = l + 0.396_337_78 * a + 0.215_803_76 * b;
+    let m_ = l - 0.105_561_346 * a - 0.063_854_17 * b;
+    let s_ = l - 0.089_484_18 * a - 1.291_485_5 * b;
+    let [l, m, s] = [l_, m_, s_].map(|c| c * c * c);
+
+    [
+        4.076_741_7 * l - 3.307_711_6 * m + 0.230_969_94 * s,
+        -1.268_438 * l + 2.609_757_4 * m - 0.341_319_38 * s,
+        -0.004_196_086_3 * l - 0.703_418_6 * m + 1.707_614_7 * s,
+    ]
+}
+
+impl ColorSpace {
+    /// Converts an sRGB color into this space.
+    pub fn encode(&self, rgba: Rgba<u8>) -> Rgba<u8> {
+        let [r, g, b, a] = rgba.0;
+        let [x, y, z] = match self {
+            ColorSpace::Srgb => return rgba,
+            ColorSpace::Linear => linear_rgb(rgba).map(|c| to_byte(c * 255.0)),
+            ColorSpace::Lab => {
+                let [l, a, b] = lab_from_linear(linear_rgb(rgba));
+                [to_byte(l * 2.55), to_byte(a + 128.0), to_byte(b + 128.0)]
+            },
+            ColorSpace::Oklab => {
+                let [l, a, b] = oklab_from_linear(linear_rgb(rgba));
+                [to_byte(l * 255.0), to_byte(a * 255.0 + 128.0), to_byte(b * 255.0 + 128.0)]
+            },
+            ColorSpace::Yuv => {
+                let [r, g, b] = [r, g, b].map(f32::from);
+                [
+                    to_byte(0.299 * r + 0.587 * g + 0.114 * b),
+                    to_byte(128.0 - 0.168_736 * r - 0.331_264 * g + 0.5 * b),
+                    to_byte(128.0 + 0.5 * r - 0.418_688 * g - 0.081_312 * b),
+                ]
+            },
+        };
+
+        Rgba([x, y, z, a])
+    }
+
+    /// Converts a color in this space back to sRGB, clamping anything out of gamut.
+    pub fn decode(&self, rgba: Rgba<u8>) -> Rgba<u8> {
+        let [x, y, z, alpha] = rgba.0;
+        let [x, y, z] = [x, y, z].map(f32::from);
+        let [r, g, b] = match self {
+            ColorSpace::Srgb => return rgba,
+            ColorSpace::Linear => [x, y, z].map(|c| linear_to_srgb(c / 255.0)),
+            ColorSpace::Lab => linear_from_lab([x / 2.55, y - 128.0, z - 128.0]).map(linear_to_srgb),
+            ColorSpace::Oklab => linear_from_oklab([x / 255.0, (y - 128.0) / 255.0, (z - 128.0) / 255.0]).map(linear_to_srgb),
+            ColorSpace::Yuv => [
+                to_byte(x + 1.402 * (z - 128.0)),
+                to_byte(x - 0.344_136 * (y - 128.0) - 0.714_136 * (z - 128.0)),
+                to_byte(x + 1.772 * (y - 128.0)),
+            ],
+        };
+
+        Rgba([r, g, b, alpha])
+    }
+
+    /// CIELAB coordinates of a color in this space, unscaled.
+    pub fn to_lab(&self, rgba: Rgba<u8>) -> [f32; 3] {
+        match self {
+            ColorSpace::Lab => {
+                let [l, a, b, _] = rgba.0.map(f32::from);
+                [l / 2.55, a - 128.0, b - 128.0]
+            },
+            color_space => lab_from_linear(linear_rgb(color_space.decode(rgba))),
+        }
+    }
+
+    /// Squared-distance weight of each encoded channel, alpha last.
+    pub fn channel_weights(&self) -> [u32; 4] {
+        match self {
+            ColorSpace::Srgb => [3, 6, 1, 10],
+            // rec. 709 luminance.
+            ColorSpace::Linear => [2, 7, 1, 10],
+            // roughly undoes the 2.55 scale on L, so this is close to plain euclidean Lab.
+            ColorSpace::Lab => [1, 6, 6, 10],
+            ColorSpace::Oklab => [3, 3, 3, 10],
+            // luma carries most of what the eye picks up.
+            ColorSpace::Yuv => [6, 2, 2, 10],
+        }
+    }
+
+    /// Weighted squared distance between two colors already in this space.
+    ///
+    /// sRGB keeps `color_diff_rgba`. The other spaces can't be premultiplied, so their
+    /// color difference is scaled by the smaller alpha instead.
+    pub fn distance(&self, lhs: &Rgba<u8>, rhs: &Rgba<u8>) -> u32 {
+        if *self == ColorSpace::Srgb {
+            return color_diff_rgba(lhs, rhs);
+        }
+        let weights = self.channel_weights();
+        let delta = |c: usize| i32::from(lhs.0[c]) - i32::from(rhs.0[c]);
+        let color: u32 = (0..3).map(|c| weights[c] * (delta(c) * delta(c)) as u32).sum();
+        let coverage = u32::from(lhs.0[3].min(rhs.0[3]));
+
+        color * coverage / 255 + weights[3] * (delta(3) * delta(3)) as u32
+    }
+}
@@ -0,0 +1,64 @@
+use std::collections::HashMap;
+use image::Rgba;
+
+use std::rc::Rc;
+use crate::core::palette_generator::PaletteGenerator;
+use crate::core::color_space::ColorSpace;
+use crate::core::rgb_helpers::{nearest_color_from_palette, ColorDistance, WeightedRgb};
+
+/// A palette that is already decided, mapping colors to their nearest entry.
+///
+/// Colors passed to `add_color` get their nearest entry computed once up front,
+/// so lookups for the image's own colors never have to scan the palette.
+pub struct FixedPalette {
+    with_alpha: bool,
+    distance: Rc<dyn ColorDistance>,
+    palette: Vec<Rgba<u8>>,
+    lookup: HashMap<Rgba<u8>, usize>,
+}
+
+impl FixedPalette {
+    pub fn new(palette: Vec<Rgba<u8>>, with_alpha: bool) -> Self {
+        Self {
+            with_alpha,
+            distance: Rc::new(WeightedRgb::new(ColorSpace::Srgb)),
+            palette,
+            lookup: HashMap::new(),
+        }
+    }
+    /// How nearest entries are measured. Defaults to `WeightedRgb` in sRGB.
+    pub fn distance(mut self, distance: Rc<dyn ColorDistance>) -> Self {
+        self.distance = distance;
+        self
+    }
+}
+
+impl PaletteGenerator for FixedPalette {
+    fn add_color(&mut self, color: Rgba<u8>) {
+        let color = self.normalize(color);
+        if !self.lookup.contains_key(&color) {
+            self.lookup.insert(color, nearest_color_from_palette(&self.palette, &color, self.distance.as_ref()));
+        }
+    }
+
+    /// The palette is fixed, `color_count` is ignored.
+    fn make_palette(&mut self, _color_count: i32) -> Vec<Rgba<u8>> {
+        self.palette.clone()
+    }
+
+    fn get_palette_index(&self, color: Rgba<u8>, force_find_color: bool) -> Option<usize> {
+        match self.lookup.get(&color) {
+            Some(&index) => Some(index),
+            None if force_find_color && !self.palette.is_empty() => Some(nearest_color_from_palette(&self.palette, &color, self.distance.as_ref())),
+            None => None,
+        }
+    }
+
+    fn color_count(&self) -> usize {
+        self.palette.len()
+    }
+
+    fn has_alpha(&self) -> bool {
+        self.with_alpha
+    }
+}
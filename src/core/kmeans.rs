@@ -0,0 +1,44 @@
+use image::Rgba;
+
+use crate::core::rgb_helpers::{nearest_color_from_palette, ColorDistance};
+
+/// Lloyd's k-means over a color histogram, seeded with `palette`.
+///
+/// Every iteration assigns each histogram color to its nearest palette entry, then moves
+/// each entry to the pixel-weighted mean of its colors. Entries nobody picked stay put.
+/// Stops after `max_iterations`, or once no entry moved more than `threshold`
+/// (in `distance` units).
+///
+/// Returns the number of iterations run.
+pub fn refine_palette(palette: &mut [Rgba<u8>], histogram: &[(Rgba<u8>, u32)], max_iterations: usize, threshold: u32, distance: &dyn ColorDistance) -> usize {
+    let mut sums = vec![[0u64; 4]; palette.len()];
+    let mut counts = vec![0u64; palette.len()];
+
+    for iteration in 0..max_iterations {
+        sums.fill([0; 4]);
+        counts.fill(0);
+        for &(color, count) in histogram {
+            let nearest = nearest_color_from_palette(palette, &color, distance);
+            for (sum, channel) in sums[nearest].iter_mut().zip(color.0) {
+                *sum += u64::from(channel) * u64::from(count);
+            }
+            counts[nearest] += u64::from(count);
+        }
+
+        let mut largest_move = 0;
+        for ((entry, sum), &count) in palette.iter_mut().zip(sums.iter()).zip(counts.iter()) {
+            if count == 0 {
+                continue;
+            }
+            let mean = Rgba(sum.map(|total| ((total + count / 2) / count) as u8));
+            largest_move = largest_move.max(distance.distance(entry, &mean));
+            *entry = mean;
+        }
+
+        if largest_move <= threshold {
+            return iteration + 1;
+        }
+    }
+
+    max_iterations
+}
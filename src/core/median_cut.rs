@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+use image::Rgba;
+
+use crate::core::color_space::ColorSpace;
+use crate::core::palette_generator::PaletteGenerator;
+use crate::core::rgb_helpers::{nearest_color_from_palette, ColorDistance, WeightedRgb};
+
+/// A run of `MedianCut::colors`, along with the channel it would be split on.
+#[derive(Clone, Copy, Debug)]
+struct ColorBox {
+    start: usize,
+    end: usize,
+    channel: usize,
+    spread: u64,
+}
+
+impl ColorBox {
+    /// Spread is weighted like `ColorSpace::distance`.
+    fn new(colors: &[(Rgba<u8>, u32)], start: usize, end: usize, channel_count: usize, color_space: ColorSpace) -> Self {
+        let mut channel = 0;
+        let mut spread = 0;
+        for (c, weight) in color_space.channel_weights().into_iter().enumerate().take(channel_count) {
+            let (min, max) = colors[start..end].iter()
+                .fold((u8::MAX, u8::MIN), |(min, max), (color, _)| (min.min(color.0[c]), max.max(color.0[c])));
+            let range = u64::from(max.saturating_sub(min));
+            let weighted = range * range * u64::from(weight);
+            if weighted > spread {
+                spread = weighted;
+                channel = c;
+            }
+        }
+
+        Self { start, end, channel, spread }
+    }
+}
+
+/// Heckbert's median cut over the color histogram: keeps splitting the box with the
+/// widest channel at its pixel-weighted median until there are enough boxes.
+pub struct MedianCut {
+    with_alpha: bool,
+    color_space: ColorSpace,
+    distance: Rc<dyn ColorDistance>,
+    histogram: HashMap<Rgba<u8>, u32>,
+    palette: Vec<Rgba<u8>>,
+    lookup: HashMap<Rgba<u8>, usize>,
+}
+
+impl Default for MedianCut {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MedianCut {
+    pub fn new() -> Self {
+        Self {
+            with_alpha: false,
+            color_space: ColorSpace::Srgb,
+            distance: Rc::new(WeightedRgb::new(ColorSpace::Srgb)),
+            histogram: HashMap::new(),
+            palette: Vec::new(),
+            lookup: HashMap::new(),
+        }
+    }
+    /// Splits on alpha as a fourth channel, so palette entries carry their own alpha.
+    pub fn with_alpha() -> Self {
+        Self {
+            with_alpha: true,
+            ..Self::new()
+        }
+    }
+    /// The space the added colors are in, which splits are weighted for.
+    pub fn color_space(mut self, color_space: ColorSpace) -> Self {
+        self.color_space = color_space;
+        self
+    }
+    /// How nearest entries are measured for colors that weren't added. Defaults to `WeightedRgb` in sRGB.
+    pub fn distance(mut self, distance: Rc<dyn ColorDistance>) -> Self {
+        self.distance = distance;
+        self
+    }
+}
+
+impl PaletteGenerator for MedianCut {
+    fn add_color(&mut self, color: Rgba<u8>) {
+        let color = self.normalize(color);
+        *self.histogram.entry(color).or_insert(0) += 1;
+    }
+
+    fn make_palette(&mut self, color_count: i32) -> Vec<Rgba<u8>> {
+        let channel_count = if self.with_alpha { 4 } else { 3 };
+        let mut colors: Vec<(Rgba<u8>, u32)> = self.histogram.iter().map(|(&color, &count)| (color, count)).collect();
+        // hashmap order is random, sorting keeps the palette deterministic.
+        colors.sort_unstable_by_key(|(color, _)| color.0);
+
+        let mut boxes = Vec::<ColorBox>::new();
+        if !colors.is_empty() {
+            boxes.push(ColorBox::new(&colors, 0, colors.len(), channel_count, self.color_space));
+        }
+
+        while boxes.len() < color_count.max(1) as usize {
+            let widest = boxes.iter()
+                .enumerate()
+                .filter(|(_, color_box)| color_box.end - color_box.start > 1 && color_box.spread > 0)
+                .max_by(|(i, a), (j, b)| a.spread.cmp(&b.spread).then(j.cmp(i)));
+            let Some((box_index, &color_box)) = widest else {
+                break;
+            };
+
+            let ColorBox { start, end, channel, .. } = color_box;
+            let run = &mut colors[start..end];
+            run.sort_unstable_by_key(|(color, _)| (color.0[channel], color.0));
+
+            let total: u64 = run.iter().map(|&(_, count)| u64::from(count)).sum();
+            let mut accumulated = 0;
+            let mut split = run.len() - 1;
+            for (i, &(_, count)) in run.iter().enumerate() {
+                accumulated += u64::from(count);
+                if accumulated * 2 >= total {
+                    split = i + 1;
+                    break;
+                }
+            }
+            let split = start + split.clamp(1, run.len() - 1);
+
+            boxes[box_index] = ColorBox::new(&colors, start, split, channel_count, self.color_space);
+            boxes.push(ColorBox::new(&colors, split, end, channel_count, self.color_space));
+        }
+
+        self.lookup.clear();
+        self.palette = boxes.iter()
+            .enumerate()
+            .map(|(palette_index, color_box)| {
+                let mut sum = [0u64; 4];
+                let mut pixel_count = 0u64;
+                for &(color, count) in &colors[color_box.start..color_box.end] {
+                    for (total, channel) in sum.iter_mut().zip(color.0) {
+                        *total += u64::from(channel) * u64::from(count);
+                    }
+                    pixel_count += u64::from(count);
+                    self.lookup.insert(color, palette_index);
+                }
+                Rgba(sum.map(|total| (total / pixel_count) as u8))
+            })
+            .collect();
+
+        self.palette.clone()
+    }
+
+    fn get_palette_index(&self, color: Rgba<u8>, force_find_color: bool) -> Option<usize> {
+        match self.lookup.get(&color) {
+            Some(&index) => Some(index),
+            None if force_find_color && !self.palette.is_empty() => Some(nearest_color_from_palette(&self.palette, &color, self.distance.as_ref())),
+            None => None,
+        }
+    }
+
+    fn color_count(&self) -> usize {
+        if self.palette.is_empty() {
+            self.histogram.len()
+        } else {
+            self.palette.len()
+        }
+    }
+
+    fn has_alpha(&self) -> bool {
+        self.with_alpha
+    }
+}
@@ -1,4 +1,11 @@
 
 pub mod accum_octree;
+pub mod color_space;
+pub mod fixed_palette;
+pub mod kmeans;
+pub mod median_cut;
+pub mod neuquant;
+pub mod palette_generator;
 pub mod rgb_helpers;
 pub mod toy_quants;
+pub mod wu;
@@ -0,0 +1,201 @@
+use std::rc::Rc;
+use image::Rgba;
+
+use crate::core::color_space::ColorSpace;
+use crate::core::fixed_palette::FixedPalette;
+use crate::core::palette_generator::PaletteGenerator;
+use crate::core::rgb_helpers::{ColorDistance, WeightedRgb};
+
+// learning rate starts at 1 and decays every cycle.
+const CYCLES: usize = 100;
+const RADIUS_DECREASE: f64 = 30.0;
+// frequency and bias learning rates, bias pushes rarely winning neurons back into the contest.
+const GAMMA: f64 = 1024.0;
+const BETA: f64 = 1.0 / GAMMA;
+const BETA_GAMMA: f64 = BETA * GAMMA;
+// primes near 500 to step through the pixels with, one of them won't divide the pixel count.
+const PRIMES: [usize; 4] = [499, 491, 487, 503];
+// Dekker's minpicturebytes: images with fewer pixels are learned whole, whatever the sample factor.
+const MIN_PIXELS: usize = PRIMES[3];
+
+/// Anthony Dekker's NeuQuant: a one-dimensional Kohonen self-organizing map of
+/// `color_count` neurons trained on a sample of the pixels.
+///
+/// Every `sample_factor`th pixel is learned, 1 being the slowest and best and 30 the fastest.
+/// Images under `MIN_PIXELS` are too small to sample and are always learned whole.
+pub struct NeuQuant {
+    with_alpha: bool,
+    color_space: ColorSpace,
+    distance: Rc<dyn ColorDistance>,
+    sample_factor: usize,
+    pixels: Vec<Rgba<u8>>,
+    lookup: Option<FixedPalette>,
+}
+
+struct Network {
+    neurons: Vec<[f64; 4]>,
+    bias: Vec<f64>,
+    frequency: Vec<f64>,
+    channel_count: usize,
+}
+
+impl Network {
+    fn new(size: usize, with_alpha: bool, color_space: ColorSpace) -> Self {
+        let neurons = (0..size)
+            .map(|i| {
+                let gray = (i * 256 / size) as u8;
+                // a few dark neurons start out transparent, so the map has somewhere to put alpha.
+                let alpha = if with_alpha && i < 16 { i as u8 * 16 } else { u8::MAX };
+                color_space.encode(Rgba([gray, gray, gray, alpha])).0.map(f64::from)
+            })
+            .collect();
+        Self {
+            neurons,
+            bias: vec![0.0; size],
+            frequency: vec![1.0 / size as f64; size],
+            channel_count: if with_alpha { 4 } else { 3 },
+        }
+    }
+
+    /// Finds the neuron closest to `color` after bias, and updates every neuron's frequency and bias.
+    fn contest(&mut self, color: &[f64; 4]) -> usize {
+        let mut best_distance = f64::MAX;
+        let mut best_biased_distance = f64::MAX;
+        let mut best = 0;
+        let mut best_biased = 0;
+
+        for (i, neuron) in self.neurons.iter().enumerate() {
+            let distance: f64 = (0..self.channel_count).map(|c| (neuron[c] - color[c]).abs()).sum();
+            if distance < best_distance {
+                best_distance = distance;
+                best = i;
+            }
+            let biased_distance = distance - self.bias[i];
+            if biased_distance < best_biased_distance {
+                best_biased_distance = biased_distance;
+                best_biased = i;
+            }
+            self.frequency[i] -= BETA * self.frequency[i];
+            self.bias[i] += BETA_GAMMA * self.frequency[i];
+        }
+        self.frequency[best] += BETA;
+        self.bias[best] -= BETA_GAMMA;
+
+        best_biased
+    }
+
+    /// Moves neuron `center` towards `color` by `alpha`, and its neighbours within `radius` by less.
+    fn alter(&mut self, alpha: f64, radius: usize, center: usize, color: &[f64; 4]) {
+        let low = center.saturating_sub(radius);
+        let high = (center + radius).min(self.neurons.len() - 1);
+        for i in low..=high {
+            let offset = i.abs_diff(center) as f64;
+            let falloff = if radius == 0 { 1.0 } else { 1.0 - (offset * offset) / (radius * radius) as f64 };
+            let neuron = &mut self.neurons[i];
+            for c in 0..4 {
+                neuron[c] -= alpha * falloff * (neuron[c] - color[c]);
+            }
+        }
+    }
+}
+
+impl NeuQuant {
+    pub fn new(sample_factor: usize) -> Self {
+        Self {
+            with_alpha: false,
+            color_space: ColorSpace::Srgb,
+            distance: Rc::new(WeightedRgb::new(ColorSpace::Srgb)),
+            sample_factor: sample_factor.clamp(1, 30),
+            pixels: Vec::new(),
+            lookup: None,
+        }
+    }
+    /// Trains on alpha as a fourth channel, so palette entries carry their own alpha.
+    pub fn with_alpha(sample_factor: usize) -> Self {
+        Self {
+            with_alpha: true,
+            ..Self::new(sample_factor)
+        }
+    }
+    /// The space the added colors are in. The network starts out as a gray ramp in it.
+    pub fn color_space(mut self, color_space: ColorSpace) -> Self {
+        self.color_space = color_space;
+        self
+    }
+    /// How colors are matched to the trained palette. Defaults to `WeightedRgb` in sRGB.
+    pub fn distance(mut self, distance: Rc<dyn ColorDistance>) -> Self {
+        self.distance = distance;
+        self
+    }
+
+    fn learn(&self, network: &mut Network) {
+        let pixel_count = self.pixels.len();
+        let sample_factor = if pixel_count < MIN_PIXELS { 1 } else { self.sample_factor };
+        let sample_count = pixel_count / sample_factor;
+        let delta = (sample_count / CYCLES).max(1);
+        let alpha_decrease = 30.0 + (sample_factor as f64 - 1.0) / 3.0;
+        let step = PRIMES.iter().copied().find(|prime| !pixel_count.is_multiple_of(*prime)).unwrap_or(PRIMES[3]);
+
+        let mut alpha = 1.0;
+        let mut radius = (network.neurons.len() / 8) as f64;
+        let mut position = 0;
+        for i in 1..=sample_count {
+            let color = self.pixels[position].0.map(f64::from);
+            let winner = network.contest(&color);
+            let whole_radius = if radius <= 1.0 { 0 } else { radius as usize };
+            network.alter(alpha, whole_radius, winner, &color);
+
+            position = (position + step) % pixel_count;
+            if i.is_multiple_of(delta) {
+                alpha -= alpha / alpha_decrease;
+                radius -= radius / RADIUS_DECREASE;
+            }
+        }
+    }
+}
+
+impl PaletteGenerator for NeuQuant {
+    fn add_color(&mut self, color: Rgba<u8>) {
+        let color = self.normalize(color);
+        self.pixels.push(color);
+    }
+
+    fn make_palette(&mut self, color_count: i32) -> Vec<Rgba<u8>> {
+        let mut network = Network::new(color_count.max(1) as usize, self.with_alpha, self.color_space);
+        if !self.pixels.is_empty() {
+            self.learn(&mut network);
+        }
+
+        let mut palette: Vec<Rgba<u8>> = network.neurons.iter()
+            .map(|neuron| Rgba(neuron.map(|channel| channel.round().clamp(0.0, 255.0) as u8)))
+            .map(|rgba| self.normalize(rgba))
+            .collect();
+        // neurons nobody trained can land on the same color, those entries would just be wasted.
+        let mut seen = std::collections::HashSet::new();
+        palette.retain(|rgba| seen.insert(*rgba));
+
+        let mut lookup = FixedPalette::new(palette.clone(), self.with_alpha).distance(Rc::clone(&self.distance));
+        for &color in &self.pixels {
+            lookup.add_color(color);
+        }
+        self.pixels = Vec::new();
+        self.lookup = Some(lookup);
+
+        palette
+    }
+
+    fn get_palette_index(&self, color: Rgba<u8>, force_find_color: bool) -> Option<usize> {
+        self.lookup.as_ref()?.get_palette_index(color, force_find_color)
+    }
+
+    fn color_count(&self) -> usize {
+        match &self.lookup {
+            Some(lookup) => lookup.color_count(),
+            None => self.pixels.len(),
+        }
+    }
+
+    fn has_alpha(&self) -> bool {
+        self.with_alpha
+    }
+}
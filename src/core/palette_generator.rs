@@ -0,0 +1,29 @@
+use image::Rgba;
+
+/// A palette algorithm: colors go in one by one, a palette of at most `color_count` comes out,
+/// and every color can then be mapped to an index of that palette.
+pub trait PaletteGenerator {
+    /// Alpha is forced to opaque unless the generator tracks alpha.
+    fn add_color(&mut self, color: Rgba<u8>);
+    /// Reduces everything added so far into a palette of at most `color_count` colors.
+    fn make_palette(&mut self, color_count: i32) -> Vec<Rgba<u8>>;
+    /// Returns the palette index for `color`. Only valid after `make_palette`.
+    ///
+    /// # Arguments
+    /// * `color` - The color value to find the palette index of.
+    /// * `force_find_color` - If `true`, a close enough index is always returned.
+    ///   If `false`, colors the generator has never seen can return a None.
+    fn get_palette_index(&self, color: Rgba<u8>, force_find_color: bool) -> Option<usize>;
+    /// Number of distinct colors held: input colors before `make_palette`, palette colors after.
+    fn color_count(&self) -> usize;
+    fn has_alpha(&self) -> bool;
+
+    /// Drops the alpha channel (to opaque) when the generator does not track alpha.
+    fn normalize(&self, color: Rgba<u8>) -> Rgba<u8> {
+        if self.has_alpha() {
+            color
+        } else {
+            Rgba([color.0[0], color.0[1], color.0[2], u8::MAX])
+        }
+    }
+}
@@ -1,6 +1,8 @@
 
 use std::ops::{AddAssign, Sub, Mul, Div};
-use image::Rgb;
+use image::{Pixel, Rgb, Rgba};
+
+use crate::core::color_space::ColorSpace;
 
 pub fn color_diff<L, R>(lhs: &Rgb<L>, rhs: &Rgb<R>) -> u32 
 where 
@@ -15,14 +17,238 @@ where
     (3 * delta_r * delta_r + 6 * delta_g * delta_g + delta_b * delta_b) as u32
 }
 
-pub fn add_colors<T, U>(color: &mut Rgb<T>, other_color: &Rgb<U>) 
+/// `color_diff` on alpha-premultiplied colors, plus the alpha difference weighted
+/// as heavily as all three color weights combined.
+///
+/// Premultiplying makes every fully transparent color equal, whatever garbage RGB it holds.
+pub fn color_diff_rgba(lhs: &Rgba<u8>, rhs: &Rgba<u8>) -> u32 {
+    let premultiply = |rgba: &Rgba<u8>| {
+        let a = i32::from(rgba.0[3]);
+        Rgb([0, 1, 2].map(|i| i32::from(rgba.0[i]) * a / 255))
+    };
+    let delta_a = i32::from(lhs.0[3]) - i32::from(rhs.0[3]);
+
+    color_diff(&premultiply(lhs), &premultiply(rhs)) + (10 * delta_a * delta_a) as u32
+}
+
+/// A way to measure how different two colors look, used wherever a nearest palette entry is picked.
+///
+/// Colors are in whatever `ColorSpace` the palette was built in. Smaller is closer,
+/// and anything under 10 counts as the same color.
+pub trait ColorDistance {
+    fn distance(&self, lhs: &Rgba<u8>, rhs: &Rgba<u8>) -> u32;
+}
+
+/// Weighted squared distance on the channels as they are stored, see `ColorSpace::distance`.
+/// In sRGB this is `color_diff_rgba`.
+pub struct WeightedRgb {
+    color_space: ColorSpace,
+}
+
+/// Squared euclidean distance in CIELAB, times 100. Orders colors like `Cie76`, without the square root.
+pub struct EuclideanLab {
+    color_space: ColorSpace,
+}
+
+/// CIE76 ΔE*ab, times 100.
+pub struct Cie76 {
+    color_space: ColorSpace,
+}
+
+/// CIE94 ΔE with graphic arts weights, times 100. `lhs` is the reference color,
+/// so it's the palette entry in `nearest_color_from_palette`.
+pub struct Cie94 {
+    color_space: ColorSpace,
+}
+
+/// CIEDE2000 ΔE, times 100.
+pub struct Ciede2000 {
+    color_space: ColorSpace,
+}
+
+impl WeightedRgb {
+    pub fn new(color_space: ColorSpace) -> Self {
+        Self { color_space }
+    }
+}
+
+impl EuclideanLab {
+    pub fn new(color_space: ColorSpace) -> Self {
+        Self { color_space }
+    }
+}
+
+impl Cie76 {
+    pub fn new(color_space: ColorSpace) -> Self {
+        Self { color_space }
+    }
+}
+
+impl Cie94 {
+    pub fn new(color_space: ColorSpace) -> Self {
+        Self { color_space }
+    }
+}
+
+impl Ciede2000 {
+    pub fn new(color_space: ColorSpace) -> Self {
+        Self { color_space }
+    }
+}
+
+impl ColorDistance for WeightedRgb {
+    fn distance(&self, lhs: &Rgba<u8>, rhs: &Rgba<u8>) -> u32 {
+        self.color_space.distance(lhs, rhs)
+    }
+}
+
+impl ColorDistance for EuclideanLab {
+    fn distance(&self, lhs: &Rgba<u8>, rhs: &Rgba<u8>) -> u32 {
+        let delta_e = cie76(self.color_space.to_lab(*lhs), self.color_space.to_lab(*rhs));
+        let (color, alpha) = lab_alpha_terms(delta_e, lhs, rhs);
+        ((color * color + alpha * alpha) * 100.0) as u32
+    }
+}
+
+impl ColorDistance for Cie76 {
+    fn distance(&self, lhs: &Rgba<u8>, rhs: &Rgba<u8>) -> u32 {
+        let delta_e = cie76(self.color_space.to_lab(*lhs), self.color_space.to_lab(*rhs));
+        let (color, alpha) = lab_alpha_terms(delta_e, lhs, rhs);
+        (color.hypot(alpha) * 100.0) as u32
+    }
+}
+
+impl ColorDistance for Cie94 {
+    fn distance(&self, lhs: &Rgba<u8>, rhs: &Rgba<u8>) -> u32 {
+        let delta_e = cie94(self.color_space.to_lab(*lhs), self.color_space.to_lab(*rhs));
+        let (color, alpha) = lab_alpha_terms(delta_e, lhs, rhs);
+        (color.hypot(alpha) * 100.0) as u32
+    }
+}
+
+impl ColorDistance for Ciede2000 {
+    fn distance(&self, lhs: &Rgba<u8>, rhs: &Rgba<u8>) -> u32 {
+        let delta_e = ciede2000(self.color_space.to_lab(*lhs), self.color_space.to_lab(*rhs));
+        let (color, alpha) = lab_alpha_terms(delta_e, lhs, rhs);
+        (color.hypot(alpha) * 100.0) as u32
+    }
+}
+
+/// The color difference scaled by the smaller alpha, and the alpha difference on the same 0-100 scale as L.
+fn lab_alpha_terms(delta_e: f32, lhs: &Rgba<u8>, rhs: &Rgba<u8>) -> (f32, f32) {
+    let coverage = f32::from(lhs.0[3].min(rhs.0[3])) / 255.0;
+    let delta_a = (f32::from(lhs.0[3]) - f32::from(rhs.0[3])) / 2.55;
+    (delta_e * coverage, delta_a)
+}
+
+/// CIE76 ΔE*ab, the euclidean distance between two Lab colors.
+pub fn cie76(lhs: [f32; 3], rhs: [f32; 3]) -> f32 {
+    let [delta_l, delta_a, delta_b] = [0, 1, 2].map(|i| lhs[i] - rhs[i]);
+    (delta_l * delta_l + delta_a * delta_a + delta_b * delta_b).sqrt()
+}
+
+/// CIE94 ΔE with the graphic arts constants (kL = 1, K1 = 0.045, K2 = 0.015), `lhs` being the reference.
+pub fn cie94(lhs: [f32; 3], rhs: [f32; 3]) -> f32 {
+    let [l1, a1, b1] = lhs;
+    let [l2, a2, b2] = rhs;
+    let c1 = a1.hypot(b1);
+    let c2 = a2.hypot(b2);
+    let delta_l = l1 - l2;
+    let delta_c = c1 - c2;
+    let delta_h_squared = ((a1 - a2).powi(2) + (b1 - b2).powi(2) - delta_c * delta_c).max(0.0);
+    let s_c = 1.0 + 0.045 * c1;
+    let s_h = 1.0 + 0.015 * c1;
+
+    (delta_l * delta_l + (delta_c / s_c).powi(2) + delta_h_squared / (s_h * s_h)).sqrt()
+}
+
+/// CIEDE2000 ΔE with kL = kC = kH = 1, following Sharma, Wu and Dalal (2005).
+pub fn ciede2000(lhs: [f32; 3], rhs: [f32; 3]) -> f32 {
+    let [l1, a1, b1] = lhs.map(f64::from);
+    let [l2, a2, b2] = rhs.map(f64::from);
+
+    let c_mean = (a1.hypot(b1) + a2.hypot(b2)) / 2.0;
+    let c_mean_7 = c_mean.powi(7);
+    let g = 0.5 * (1.0 - (c_mean_7 / (c_mean_7 + 25f64.powi(7))).sqrt());
+    let a1 = (1.0 + g) * a1;
+    let a2 = (1.0 + g) * a2;
+    let c1 = a1.hypot(b1);
+    let c2 = a2.hypot(b2);
+    let hue = |a: f64, b: f64| if a == 0.0 && b == 0.0 { 0.0 } else { b.atan2(a).to_degrees().rem_euclid(360.0) };
+    let h1 = hue(a1, b1);
+    let h2 = hue(a2, b2);
+
+    let delta_l = l2 - l1;
+    let delta_c = c2 - c1;
+    let delta_h = if c1 * c2 == 0.0 {
+        0.0
+    } else if (h2 - h1).abs() <= 180.0 {
+        h2 - h1
+    } else if h2 - h1 > 180.0 {
+        h2 - h1 - 360.0
+    } else {
+        h2 - h1 + 360.0
+    };
+    let delta_h = 2.0 * (c1 * c2).sqrt() * (delta_h / 2.0).to_radians().sin();
+
+    let l_mean = (l1 + l2) / 2.0;
+    let c_mean = (c1 + c2) / 2.0;
+    let h_mean = if c1 * c2 == 0.0 {
+        h1 + h2
+    } else if (h1 - h2).abs() <= 180.0 {
+        (h1 + h2) / 2.0
+    } else if h1 + h2 < 360.0 {
+        (h1 + h2 + 360.0) / 2.0
+    } else {
+        (h1 + h2 - 360.0) / 2.0
+    };
+
+    let t = 1.0 - 0.17 * (h_mean - 30.0).to_radians().cos()
+        + 0.24 * (2.0 * h_mean).to_radians().cos()
+        + 0.32 * (3.0 * h_mean + 6.0).to_radians().cos()
+        - 0.20 * (4.0 * h_mean - 63.0).to_radians().cos();
+    let delta_theta = 30.0 * (-((h_mean - 275.0) / 25.0).powi(2)).exp();
+    let c_mean_7 = c_mean.powi(7);
+    let r_c = 2.0 * (c_mean_7 / (c_mean_7 + 25f64.powi(7))).sqrt();
+    let s_l = 1.0 + 0.015 * (l_mean - 50.0).powi(2) / (20.0 + (l_mean - 50.0).powi(2)).sqrt();
+    let s_c = 1.0 + 0.045 * c_mean;
+    let s_h = 1.0 + 0.015 * c_mean * t;
+    let r_t = -(2.0 * delta_theta).to_radians().sin() * r_c;
+
+    let l_term = delta_l / s_l;
+    let c_term = delta_c / s_c;
+    let h_term = delta_h / s_h;
+    (l_term * l_term + c_term * c_term + h_term * h_term + r_t * c_term * h_term).sqrt() as f32
+}
+
+// expensive function to account for the octree not covering all colors!
+// dithering makes new colors out of nowhere due to errors + original color = new color
+pub fn nearest_color_from_palette(palette: &[Rgba<u8>], rgba: &Rgba<u8>, distance: &dyn ColorDistance) -> usize {
+    let mut smallest_diff = u32::MAX;
+    let mut best_index: usize = 0;
+    for (i, palette_rgba) in palette.iter().enumerate() {
+        let diff = distance.distance(palette_rgba, rgba);
+        if diff < 10 {
+            return i;
+        }
+        if diff < smallest_diff {
+            smallest_diff = diff;
+            best_index = i;
+        }
+    }
+
+    best_index
+}
+
+pub fn add_colors<P, Q>(color: &mut P, other_color: &Q) 
 where
-    T: AddAssign + From<U>,
-    U: Copy,
+    P: Pixel,
+    Q: Pixel,
+    P::Subpixel: AddAssign + From<Q::Subpixel>,
 {
-    color.0.iter_mut()
-        .zip(other_color.0.iter().copied())
-        .for_each(|(a, b)| *a += T::from(b));
+    color.channels_mut().iter_mut()
+        .zip(other_color.channels().iter().copied())
+        .for_each(|(a, b)| *a += P::Subpixel::from(b));
 }
 
 pub fn sub_colors<T, U>(color: &Rgb<T>, other_color: &Rgb<U>) -> Rgb<T> 
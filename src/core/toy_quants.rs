@@ -79,15 +79,15 @@ fn bw_quant_floyd_seinberg_dither(source: &DynamicImage, destination: &mut RgbaI
             + f32::from(rgba[2]) * 0.0722)
         .round()
         .clamp(0.0, 255.0) as i16;
-        let corrected_grayscale = (grayscale + current_errors[x as usize]).clamp(0, 255);
+        let corrected_grayscale = (grayscale + current_errors[x]).clamp(0, 255);
         let bw_color: u8 = if corrected_grayscale <= 127 { 0 } else { 255 };
 
         let color_error = corrected_grayscale - i16::from(bw_color);
 
         // https://tannerhelland.com/2012/12/28/dithering-eleven-algorithms-source-code.html
         // https://www.youtube.com/watch?v=ico4fJfohMQ
-        let forward_x = (x + 1).clamp(0, image_width) as usize;
-        let behind_x = if x <= 0 { 0 } else { (x - 1).clamp(0, image_width) as usize };
+        let forward_x = (x + 1).clamp(0, image_width);
+        let behind_x = if x == 0 { 0 } else { (x - 1).clamp(0, image_width) };
         current_errors[forward_x] += color_error * 7 / 16;
         forward_errors[behind_x] += color_error * 3 / 16;
         forward_errors[x] += color_error * 5 / 16;
@@ -118,15 +118,15 @@ fn bw_quant_sierra_lite_dither(source: &DynamicImage, destination: &mut RgbaImag
             + f32::from(rgba[2]) * 0.0722)
         .round()
         .clamp(0.0, 255.0) as i16;
-        let corrected_grayscale = (grayscale + current_errors[x as usize]).clamp(0, 255);
+        let corrected_grayscale = (grayscale + current_errors[x]).clamp(0, 255);
         let bw_color: u8 = if corrected_grayscale <= 127 { 0 } else { 255 };
 
         let color_error = corrected_grayscale - i16::from(bw_color);
 
         // https://tannerhelland.com/2012/12/28/dithering-eleven-algorithms-source-code.html
         // https://www.youtube.com/watch?v=ico4fJfohMQ
-        let forward_x = (x + 1).clamp(0, image_width) as usize;
-        let behind_x = if x <= 0 { 0 } else { (x - 1).clamp(0, image_width) as usize };
+        let forward_x = (x + 1).clamp(0, image_width);
+        let behind_x = if x == 0 { 0 } else { (x - 1).clamp(0, image_width) };
         current_errors[forward_x] += color_error * 2 / 4;
         forward_errors[behind_x] += color_error / 4;
         forward_errors[x] += color_error / 4;
@@ -158,7 +158,7 @@ fn sierra_lite(source: &DynamicImage, destination: &mut RgbaImage) {
         .clamp(0.0, 255.0) as i16;
 
         // corrected grayscale will probably be adding the rgb values then averaging them (TODO)
-        let corrected_grayscale = (grayscale + current_errors[x as usize]).clamp(0, 255);
+        let corrected_grayscale = (grayscale + current_errors[x]).clamp(0, 255);
         // pushing them back into the octree to get another value (TODO)
         let bw_color: u8 = if corrected_grayscale <= 127 { 0 } else { 255 };
 
@@ -167,8 +167,8 @@ fn sierra_lite(source: &DynamicImage, destination: &mut RgbaImage) {
 
         // https://tannerhelland.com/2012/12/28/dithering-eleven-algorithms-source-code.html
         // https://www.youtube.com/watch?v=ico4fJfohMQ
-        let forward_x = (x + 1).clamp(0, image_width) as usize;
-        let behind_x = if x <= 0 { 0 } else { (x - 1).clamp(0, image_width) as usize };
+        let forward_x = (x + 1).clamp(0, image_width);
+        let behind_x = if x == 0 { 0 } else { (x - 1).clamp(0, image_width) };
         current_errors[forward_x] += color_error * 2 / 4;
         forward_errors[behind_x] += color_error / 4;
         forward_errors[x] += color_error / 4;
@@ -0,0 +1,301 @@
+use image::Rgba;
+
+use crate::core::palette_generator::PaletteGenerator;
+
+// 5 bits per channel, plus a zero row/column/plane for the cumulative moments.
+const SIDE: usize = 33;
+
+fn cell_index(r: usize, g: usize, b: usize) -> usize {
+    (r * SIDE + g) * SIDE + b
+}
+
+fn histogram_index(color: Rgba<u8>) -> usize {
+    let [r, g, b, _] = color.0.map(|c| usize::from(c >> 3) + 1);
+    cell_index(r, g, b)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Direction {
+    Red,
+    Green,
+    Blue,
+}
+
+/// A box of histogram cells, lower bounds exclusive and upper bounds inclusive.
+#[derive(Clone, Copy, Debug, Default)]
+struct WuBox {
+    r0: usize,
+    r1: usize,
+    g0: usize,
+    g1: usize,
+    b0: usize,
+    b1: usize,
+    vol: usize,
+}
+
+/// Xiaolin Wu's quantizer: builds cumulative color moments over a 32x32x32 histogram,
+/// then greedily splits the box with the largest variance where the split minimizes
+/// the summed variance of both halves.
+///
+/// Alpha is never part of the boxes, every palette entry is opaque.
+pub struct WuQuantizer {
+    weights: Vec<f64>,
+    moments_r: Vec<f64>,
+    moments_g: Vec<f64>,
+    moments_b: Vec<f64>,
+    moments_2: Vec<f64>,
+    tags: Vec<u16>,
+    palette_len: usize,
+}
+
+impl Default for WuQuantizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WuQuantizer {
+    pub fn new() -> Self {
+        let cells = SIDE * SIDE * SIDE;
+        Self {
+            weights: vec![0.0; cells],
+            moments_r: vec![0.0; cells],
+            moments_g: vec![0.0; cells],
+            moments_b: vec![0.0; cells],
+            moments_2: vec![0.0; cells],
+            tags: vec![0; cells],
+            palette_len: 0,
+        }
+    }
+
+    /// Turns the histogram into cumulative moments, so any box sum is 8 lookups.
+    fn cumulate_moments(&mut self) {
+        for r in 1..SIDE {
+            let mut area = [[0.0f64; 5]; SIDE];
+            for g in 1..SIDE {
+                let mut line = [0.0f64; 5];
+                for (b, area) in area.iter_mut().enumerate().skip(1) {
+                    let index = cell_index(r, g, b);
+                    let previous = cell_index(r - 1, g, b);
+                    for (m, moments) in [&mut self.weights, &mut self.moments_r, &mut self.moments_g, &mut self.moments_b, &mut self.moments_2].into_iter().enumerate() {
+                        line[m] += moments[index];
+                        area[m] += line[m];
+                        moments[index] = moments[previous] + area[m];
+                    }
+                }
+            }
+        }
+    }
+
+    fn volume(cube: &WuBox, moments: &[f64]) -> f64 {
+        moments[cell_index(cube.r1, cube.g1, cube.b1)]
+            - moments[cell_index(cube.r1, cube.g1, cube.b0)]
+            - moments[cell_index(cube.r1, cube.g0, cube.b1)]
+            + moments[cell_index(cube.r1, cube.g0, cube.b0)]
+            - moments[cell_index(cube.r0, cube.g1, cube.b1)]
+            + moments[cell_index(cube.r0, cube.g1, cube.b0)]
+            + moments[cell_index(cube.r0, cube.g0, cube.b1)]
+            - moments[cell_index(cube.r0, cube.g0, cube.b0)]
+    }
+
+    /// The part of `volume` that does not depend on the cut position along `direction`.
+    fn bottom(cube: &WuBox, direction: Direction, moments: &[f64]) -> f64 {
+        match direction {
+            Direction::Red => -moments[cell_index(cube.r0, cube.g1, cube.b1)]
+                + moments[cell_index(cube.r0, cube.g1, cube.b0)]
+                + moments[cell_index(cube.r0, cube.g0, cube.b1)]
+                - moments[cell_index(cube.r0, cube.g0, cube.b0)],
+            Direction::Green => -moments[cell_index(cube.r1, cube.g0, cube.b1)]
+                + moments[cell_index(cube.r1, cube.g0, cube.b0)]
+                + moments[cell_index(cube.r0, cube.g0, cube.b1)]
+                - moments[cell_index(cube.r0, cube.g0, cube.b0)],
+            Direction::Blue => -moments[cell_index(cube.r1, cube.g1, cube.b0)]
+                + moments[cell_index(cube.r1, cube.g0, cube.b0)]
+                + moments[cell_index(cube.r0, cube.g1, cube.b0)]
+                - moments[cell_index(cube.r0, cube.g0, cube.b0)],
+        }
+    }
+
+    /// The part of `volume` that depends on the cut position along `direction`.
+    fn top(cube: &WuBox, direction: Direction, position: usize, moments: &[f64]) -> f64 {
+        match direction {
+            Direction::Red => moments[cell_index(position, cube.g1, cube.b1)]
+                - moments[cell_index(position, cube.g1, cube.b0)]
+                - moments[cell_index(position, cube.g0, cube.b1)]
+                + moments[cell_index(position, cube.g0, cube.b0)],
+            Direction::Green => moments[cell_index(cube.r1, position, cube.b1)]
+                - moments[cell_index(cube.r1, position, cube.b0)]
+                - moments[cell_index(cube.r0, position, cube.b1)]
+                + moments[cell_index(cube.r0, position, cube.b0)],
+            Direction::Blue => moments[cell_index(cube.r1, cube.g1, position)]
+                - moments[cell_index(cube.r1, cube.g0, position)]
+                - moments[cell_index(cube.r0, cube.g1, position)]
+                + moments[cell_index(cube.r0, cube.g0, position)],
+        }
+    }
+
+    fn variance(&self, cube: &WuBox) -> f64 {
+        let dr = Self::volume(cube, &self.moments_r);
+        let dg = Self::volume(cube, &self.moments_g);
+        let db = Self::volume(cube, &self.moments_b);
+        let xx = Self::volume(cube, &self.moments_2);
+
+        xx - (dr * dr + dg * dg + db * db) / Self::volume(cube, &self.weights)
+    }
+
+    /// Finds the cut along `direction` that maximizes the between-halves variance.
+    /// Returns `None` when every cut leaves one half empty.
+    fn maximize(&self, cube: &WuBox, direction: Direction, first: usize, last: usize, whole: [f64; 4]) -> Option<(f64, usize)> {
+        let base = [&self.moments_r, &self.moments_g, &self.moments_b, &self.weights]
+            .map(|moments| Self::bottom(cube, direction, moments));
+        let mut best: Option<(f64, usize)> = None;
+
+        for position in first..last {
+            let half = [&self.moments_r, &self.moments_g, &self.moments_b, &self.weights]
+                .map(|moments| Self::top(cube, direction, position, moments));
+            let [half_r, half_g, half_b, half_w] = [0, 1, 2, 3].map(|i| base[i] + half[i]);
+            if half_w == 0.0 {
+                continue;
+            }
+            let other_w = whole[3] - half_w;
+            if other_w == 0.0 {
+                continue;
+            }
+            let [other_r, other_g, other_b] = [0, 1, 2].map(|i| whole[i] - [half_r, half_g, half_b][i]);
+            let score = (half_r * half_r + half_g * half_g + half_b * half_b) / half_w
+                + (other_r * other_r + other_g * other_g + other_b * other_b) / other_w;
+
+            if best.is_none_or(|(max, _)| score > max) {
+                best = Some((score, position));
+            }
+        }
+
+        best
+    }
+
+    /// Splits `cube` in two along its best cut, or returns `None` if it can't be split.
+    fn cut(&self, cube: &mut WuBox) -> Option<WuBox> {
+        let whole = [&self.moments_r, &self.moments_g, &self.moments_b, &self.weights]
+            .map(|moments| Self::volume(cube, moments));
+
+        let candidates = [
+            (Direction::Red, self.maximize(cube, Direction::Red, cube.r0 + 1, cube.r1, whole)),
+            (Direction::Green, self.maximize(cube, Direction::Green, cube.g0 + 1, cube.g1, whole)),
+            (Direction::Blue, self.maximize(cube, Direction::Blue, cube.b0 + 1, cube.b1, whole)),
+        ];
+        let mut best: Option<(Direction, f64, usize)> = None;
+        for (direction, result) in candidates {
+            if let Some((score, position)) = result && best.is_none_or(|(_, max, _)| score > max) {
+                best = Some((direction, score, position));
+            }
+        }
+        let (direction, _, position) = best?;
+
+        let mut other = WuBox { r1: cube.r1, g1: cube.g1, b1: cube.b1, ..WuBox::default() };
+        match direction {
+            Direction::Red => {
+                other.r0 = position;
+                cube.r1 = position;
+                other.g0 = cube.g0;
+                other.b0 = cube.b0;
+            },
+            Direction::Green => {
+                other.g0 = position;
+                cube.g1 = position;
+                other.r0 = cube.r0;
+                other.b0 = cube.b0;
+            },
+            Direction::Blue => {
+                other.b0 = position;
+                cube.b1 = position;
+                other.r0 = cube.r0;
+                other.g0 = cube.g0;
+            },
+        }
+        cube.vol = (cube.r1 - cube.r0) * (cube.g1 - cube.g0) * (cube.b1 - cube.b0);
+        other.vol = (other.r1 - other.r0) * (other.g1 - other.g0) * (other.b1 - other.b0);
+
+        Some(other)
+    }
+}
+
+impl PaletteGenerator for WuQuantizer {
+    fn add_color(&mut self, color: Rgba<u8>) {
+        let index = histogram_index(color);
+        let [r, g, b, _] = color.0.map(f64::from);
+        self.weights[index] += 1.0;
+        self.moments_r[index] += r;
+        self.moments_g[index] += g;
+        self.moments_b[index] += b;
+        self.moments_2[index] += r * r + g * g + b * b;
+    }
+
+    fn make_palette(&mut self, color_count: i32) -> Vec<Rgba<u8>> {
+        self.cumulate_moments();
+
+        let color_count = color_count.max(1) as usize;
+        let mut cubes = vec![WuBox { r1: SIDE - 1, g1: SIDE - 1, b1: SIDE - 1, ..WuBox::default() }];
+        let mut variances = vec![0.0f64];
+        let mut next = 0;
+
+        while cubes.len() < color_count {
+            match self.cut(&mut cubes[next]) {
+                Some(other) => {
+                    variances[next] = if cubes[next].vol > 1 { self.variance(&cubes[next]) } else { 0.0 };
+                    variances.push(if other.vol > 1 { self.variance(&other) } else { 0.0 });
+                    cubes.push(other);
+                },
+                None => variances[next] = 0.0,
+            }
+
+            let (largest, &largest_variance) = variances.iter()
+                .enumerate()
+                .fold((0, &variances[0]), |best, candidate| if candidate.1 > best.1 { candidate } else { best });
+            if largest_variance <= 0.0 {
+                break;
+            }
+            next = largest;
+        }
+
+        let mut palette = Vec::with_capacity(cubes.len());
+        for (palette_index, cube) in cubes.iter().enumerate() {
+            for r in (cube.r0 + 1)..=cube.r1 {
+                for g in (cube.g0 + 1)..=cube.g1 {
+                    for b in (cube.b0 + 1)..=cube.b1 {
+                        self.tags[cell_index(r, g, b)] = palette_index as u16;
+                    }
+                }
+            }
+
+            let weight = Self::volume(cube, &self.weights);
+            let rgba = if weight > 0.0 {
+                let [r, g, b] = [&self.moments_r, &self.moments_g, &self.moments_b]
+                    .map(|moments| (Self::volume(cube, moments) / weight) as u8);
+                Rgba([r, g, b, u8::MAX])
+            } else {
+                Rgba([0, 0, 0, u8::MAX])
+            };
+            palette.push(rgba);
+        }
+        self.palette_len = palette.len();
+
+        palette
+    }
+
+    /// Every histogram cell belongs to exactly one box, so this always finds a color.
+    fn get_palette_index(&self, color: Rgba<u8>, _force_find_color: bool) -> Option<usize> {
+        Some(usize::from(self.tags[histogram_index(color)]))
+    }
+
+    fn color_count(&self) -> usize {
+        if self.palette_len > 0 {
+            self.palette_len
+        } else {
+            self.weights.iter().filter(|&&weight| weight > 0.0).count()
+        }
+    }
+
+    fn has_alpha(&self) -> bool {
+        false
+    }
+}
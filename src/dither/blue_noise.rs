@@ -0,0 +1,132 @@
+/// Width of the gaussian that measures how crowded a spot is, as in Ulichney's paper.
+const SIGMA: f32 = 1.5;
+/// The initial binary pattern sets about one pixel in ten.
+const INITIAL_DENSITY: usize = 10;
+
+/// xorshift32, so every mask of a given size comes out the same.
+struct Rng(u32);
+
+impl Rng {
+    fn next(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0
+    }
+}
+
+/// How crowded every pixel's neighbourhood is: the sum of a gaussian centred on every set pixel,
+/// wrapping around the edges so the mask tiles seamlessly.
+struct Density {
+    size: usize,
+    kernel: Vec<f32>,
+    density: Vec<f32>,
+}
+
+impl Density {
+    fn new(size: usize, pattern: &[bool]) -> Self {
+        let wrapped = |d: usize| d.min(size - d) as f32;
+        let kernel = (0..size * size)
+            .map(|i| {
+                let (dx, dy) = (wrapped(i % size), wrapped(i / size));
+                (-(dx * dx + dy * dy) / (2.0 * SIGMA * SIGMA)).exp()
+            })
+            .collect();
+        let mut density = Self { size, kernel, density: vec![0.0; size * size] };
+        for (i, _) in pattern.iter().enumerate().filter(|&(_, &set)| set) {
+            density.toggle(i, 1.0);
+        }
+
+        density
+    }
+
+    fn toggle(&mut self, index: usize, sign: f32) {
+        let size = self.size;
+        let (x, y) = (index % size, index / size);
+        for (i, density) in self.density.iter_mut().enumerate() {
+            let dx = (i % size + size - x) % size;
+            let dy = (i / size + size - y) % size;
+            *density += sign * self.kernel[dy * size + dx];
+        }
+    }
+
+    /// The set pixel with the most crowded neighbourhood.
+    fn tightest_cluster(&self, pattern: &[bool]) -> usize {
+        self.pick(pattern, true, |lhs, rhs| lhs > rhs)
+    }
+
+    /// The unset pixel with the emptiest neighbourhood.
+    fn largest_void(&self, pattern: &[bool]) -> usize {
+        self.pick(pattern, false, |lhs, rhs| lhs < rhs)
+    }
+
+    // the first pixel wins ties, keeping the mask deterministic.
+    fn pick(&self, pattern: &[bool], set: bool, better: impl Fn(f32, f32) -> bool) -> usize {
+        let mut best: Option<usize> = None;
+        for (i, &density) in self.density.iter().enumerate() {
+            if pattern[i] == set && best.is_none_or(|best| better(density, self.density[best])) {
+                best = Some(i);
+            }
+        }
+        best.expect("pattern has no pixel to pick")
+    }
+}
+
+/// Generates a `size`x`size` blue noise mask with Ulichney's void-and-cluster method,
+/// in row-major order, holding every value in `0..size²` once.
+///
+/// A seeded random pattern is first relaxed by moving its most crowded pixel into the
+/// biggest hole until that stops changing anything. Ranks then count down while pixels are
+/// pulled out of the tightest clusters, and up while the largest voids are filled.
+/// Thresholding the mask at any level gives evenly spread pixels without a visible grid.
+///
+/// Takes O(size⁴) time, so big masks are better generated once and passed in as an image.
+/// Panics if `size` is below 2.
+pub fn void_and_cluster(size: usize) -> Vec<u32> {
+    assert!(size >= 2, "blue noise masks must be at least 2x2");
+    let pixel_count = size * size;
+    let initial_count = (pixel_count / INITIAL_DENSITY).max(1);
+
+    let mut rng = Rng(0x9e37_79b9);
+    let mut pattern = vec![false; pixel_count];
+    let mut placed = 0;
+    while placed < initial_count {
+        let i = rng.next() as usize % pixel_count;
+        if !pattern[i] {
+            pattern[i] = true;
+            placed += 1;
+        }
+    }
+    let mut density = Density::new(size, &pattern);
+    for _ in 0..pixel_count {
+        let cluster = density.tightest_cluster(&pattern);
+        pattern[cluster] = false;
+        density.toggle(cluster, -1.0);
+        let void = density.largest_void(&pattern);
+        pattern[void] = true;
+        density.toggle(void, 1.0);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut ranks = vec![0; pixel_count];
+    let mut prototype = pattern.clone();
+    let mut prototype_density = Density::new(size, &prototype);
+    for rank in (0..initial_count).rev() {
+        let cluster = prototype_density.tightest_cluster(&prototype);
+        prototype[cluster] = false;
+        prototype_density.toggle(cluster, -1.0);
+        ranks[cluster] = rank as u32;
+    }
+    // past half full, the tightest cluster of unset pixels is the largest void of set ones,
+    // so filling voids carries on all the way.
+    for rank in initial_count..pixel_count {
+        let void = density.largest_void(&pattern);
+        pattern[void] = true;
+        density.toggle(void, 1.0);
+        ranks[void] = rank as u32;
+    }
+
+    ranks
+}
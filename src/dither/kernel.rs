@@ -0,0 +1,203 @@
+use std::str::FromStr;
+
+use thiserror::Error;
+
+/// One weight of a diffusion kernel, `dx` columns and `dy` rows away from the current pixel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KernelTap {
+    pub dx: i32,
+    pub dy: u32,
+    pub weight: u32,
+}
+
+/// Where the quantization error of a pixel goes: every tap receives `weight / divisor` of it.
+///
+/// Taps only ever point at pixels that haven't been visited yet, to the right on the current
+/// row or anywhere on the rows below. Weights adding up to less than the divisor throw
+/// the rest of the error away, as Atkinson does.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DiffusionKernel {
+    taps: Vec<KernelTap>,
+    divisor: u32,
+}
+
+#[derive(Error, Debug)]
+pub enum KernelError {
+    #[error("The kernel has no weights.")]
+    Empty,
+    #[error("The kernel divisor can't be 0.")]
+    ZeroDivisor,
+    #[error("Tap ({0}, {1}) points at a pixel that was already dithered.")]
+    BackwardTap(i32, u32),
+    #[error("The kernel needs exactly one `*` marking the current pixel, on its first row.")]
+    CurrentPixel,
+    #[error("Every kernel row needs as many cells as the first one.")]
+    RaggedRows,
+    #[error("{0} is not a kernel weight.")]
+    InvalidWeight(String),
+    #[error("Kernel weights and divisors go up to {max}, but this one is {0}.", max = i32::MAX)]
+    TooLarge(u32),
+    #[error("The weights add up to {0}, more than the divisor {1}, which would amplify the error.")]
+    WeightsExceedDivisor(u64, u32),
+}
+
+// (dx, dy, weight) tables, with their divisors.
+const FLOYD_STEINBERG: &[(i32, u32, u32)] = &[
+    (1, 0, 7),
+    (-1, 1, 3), (0, 1, 5), (1, 1, 1),
+];
+const SIERRA_LITE: &[(i32, u32, u32)] = &[
+    (1, 0, 2),
+    (-1, 1, 1), (0, 1, 1),
+];
+const JARVIS_JUDICE_NINKE: &[(i32, u32, u32)] = &[
+    (1, 0, 7), (2, 0, 5),
+    (-2, 1, 3), (-1, 1, 5), (0, 1, 7), (1, 1, 5), (2, 1, 3),
+    (-2, 2, 1), (-1, 2, 3), (0, 2, 5), (1, 2, 3), (2, 2, 1),
+];
+const STUCKI: &[(i32, u32, u32)] = &[
+    (1, 0, 8), (2, 0, 4),
+    (-2, 1, 2), (-1, 1, 4), (0, 1, 8), (1, 1, 4), (2, 1, 2),
+    (-2, 2, 1), (-1, 2, 2), (0, 2, 4), (1, 2, 2), (2, 2, 1),
+];
+const BURKES: &[(i32, u32, u32)] = &[
+    (1, 0, 8), (2, 0, 4),
+    (-2, 1, 2), (-1, 1, 4), (0, 1, 8), (1, 1, 4), (2, 1, 2),
+];
+const SIERRA_3: &[(i32, u32, u32)] = &[
+    (1, 0, 5), (2, 0, 3),
+    (-2, 1, 2), (-1, 1, 4), (0, 1, 5), (1, 1, 4), (2, 1, 2),
+    (-1, 2, 2), (0, 2, 3), (1, 2, 2),
+];
+const TWO_ROW_SIERRA: &[(i32, u32, u32)] = &[
+    (1, 0, 4), (2, 0, 3),
+    (-2, 1, 1), (-1, 1, 2), (0, 1, 3), (1, 1, 2), (2, 1, 1),
+];
+const ATKINSON: &[(i32, u32, u32)] = &[
+    (1, 0, 1), (2, 0, 1),
+    (-1, 1, 1), (0, 1, 1), (1, 1, 1),
+    (0, 2, 1),
+];
+const SHIAU_FAN: &[(i32, u32, u32)] = &[
+    (1, 0, 4),
+    (-2, 1, 1), (-1, 1, 1), (0, 1, 2),
+];
+const SHIAU_FAN_2: &[(i32, u32, u32)] = &[
+    (1, 0, 8),
+    (-3, 1, 1), (-2, 1, 1), (-1, 1, 2), (0, 1, 4),
+];
+
+impl DiffusionKernel {
+    /// Checks that there is at least one nonzero weight, that the divisor isn't 0, that the
+    /// weights add up to no more than the divisor, both fitting an `i32`, and that no tap
+    /// points back at a pixel that was already dithered. Zero weights are dropped.
+    pub fn new(taps: Vec<KernelTap>, divisor: u32) -> Result<Self, KernelError> {
+        let taps: Vec<KernelTap> = taps.into_iter().filter(|tap| tap.weight > 0).collect();
+        if taps.is_empty() {
+            return Err(KernelError::Empty);
+        }
+        if divisor == 0 {
+            return Err(KernelError::ZeroDivisor);
+        }
+        if let Some(too_large) = taps.iter().map(|tap| tap.weight).chain([divisor]).find(|&value| i32::try_from(value).is_err()) {
+            return Err(KernelError::TooLarge(too_large));
+        }
+        let weight_sum: u64 = taps.iter().map(|tap| u64::from(tap.weight)).sum();
+        if weight_sum > u64::from(divisor) {
+            return Err(KernelError::WeightsExceedDivisor(weight_sum, divisor));
+        }
+        if let Some(tap) = taps.iter().find(|tap| tap.dy == 0 && tap.dx <= 0) {
+            return Err(KernelError::BackwardTap(tap.dx, tap.dy));
+        }
+
+        Ok(Self { taps, divisor })
+    }
+
+    fn from_table(table: &[(i32, u32, u32)], divisor: u32) -> Self {
+        let taps = table.iter().map(|&(dx, dy, weight)| KernelTap { dx, dy, weight }).collect();
+        Self::new(taps, divisor).expect("built-in kernels are valid")
+    }
+
+    pub fn floyd_steinberg() -> Self { Self::from_table(FLOYD_STEINBERG, 16) }
+    pub fn sierra_lite() -> Self { Self::from_table(SIERRA_LITE, 4) }
+    pub fn jarvis_judice_ninke() -> Self { Self::from_table(JARVIS_JUDICE_NINKE, 48) }
+    pub fn stucki() -> Self { Self::from_table(STUCKI, 42) }
+    pub fn burkes() -> Self { Self::from_table(BURKES, 32) }
+    pub fn sierra_3() -> Self { Self::from_table(SIERRA_3, 32) }
+    pub fn two_row_sierra() -> Self { Self::from_table(TWO_ROW_SIERRA, 16) }
+    /// Only spreads 6/8 of the error, trading shadow and highlight detail for contrast.
+    pub fn atkinson() -> Self { Self::from_table(ATKINSON, 8) }
+    pub fn shiau_fan() -> Self { Self::from_table(SHIAU_FAN, 8) }
+    /// The five cell Shiau-Fan kernel, with even fewer worm artifacts.
+    pub fn shiau_fan_2() -> Self { Self::from_table(SHIAU_FAN_2, 16) }
+
+    pub fn taps(&self) -> &[KernelTap] {
+        &self.taps
+    }
+
+    pub fn divisor(&self) -> u32 {
+        self.divisor
+    }
+
+    /// How many rows the kernel touches, the current one included.
+    pub fn rows(&self) -> usize {
+        self.taps.iter().map(|tap| tap.dy as usize).max().unwrap_or(0) + 1
+    }
+}
+
+/// Parses a kernel written out as a grid, the way kernels are usually printed:
+///
+/// ```text
+/// - * 7
+/// 3 5 1
+/// /16
+/// ```
+///
+/// Rows are separated by newlines or `;`, and cells by whitespace. `*` marks the current pixel
+/// and must sit on the first row, `-` is an empty cell, and `/n` sets the divisor, which is
+/// otherwise the sum of the weights. `#` starts a comment, so kernels can live in a file.
+impl FromStr for DiffusionKernel {
+    type Err = KernelError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut divisor = None;
+        let mut rows: Vec<Vec<&str>> = Vec::new();
+        for line in s.lines().flat_map(|line| line.split('#').next().unwrap_or_default().split(';')) {
+            let mut cells = Vec::new();
+            for cell in line.split_whitespace() {
+                match cell.strip_prefix('/') {
+                    Some(value) => divisor = Some(value.parse::<u32>().map_err(|_| KernelError::InvalidWeight(cell.to_string()))?),
+                    None => cells.push(cell),
+                }
+            }
+            if !cells.is_empty() {
+                rows.push(cells);
+            }
+        }
+
+        let current_pixels = rows.iter().flatten().filter(|&&cell| cell == "*").count();
+        let origin = match rows.first().and_then(|row| row.iter().position(|&cell| cell == "*")) {
+            Some(origin) if current_pixels == 1 => origin,
+            _ => return Err(KernelError::CurrentPixel),
+        };
+        let width = rows[0].len();
+        if rows.iter().any(|row| row.len() != width) {
+            return Err(KernelError::RaggedRows);
+        }
+
+        let mut taps = Vec::new();
+        for (dy, row) in rows.iter().enumerate() {
+            for (column, &cell) in row.iter().enumerate() {
+                if cell == "*" || cell == "-" {
+                    continue;
+                }
+                let weight = cell.parse::<u32>().map_err(|_| KernelError::InvalidWeight(cell.to_string()))?;
+                taps.push(KernelTap { dx: column as i32 - origin as i32, dy: dy as u32, weight });
+            }
+        }
+        // a sum too large for a divisor is clamped, `new` then rejects it as above the divisor.
+        let divisor = divisor.unwrap_or_else(|| taps.iter().map(|tap| u64::from(tap.weight)).sum::<u64>().min(i32::MAX as u64) as u32);
+
+        Self::new(taps, divisor)
+    }
+}
@@ -0,0 +1,375 @@
+pub mod blue_noise;
+pub mod kernel;
+pub mod ordered;
+pub mod pattern;
+pub mod riemersma;
+
+use std::collections::VecDeque;
+
+use image::{Rgb, Rgba, RgbaImage};
+
+use crate::core::color_space::{from_linear_light, to_linear_light, LINEAR_LIGHT_MAX};
+use crate::core::palette_generator::PaletteGenerator;
+use crate::core::rgb_helpers::{nearest_color_from_palette, ColorDistance};
+use crate::dither::kernel::DiffusionKernel;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DitherMode {
+    Base,
+    FloydSteinberg,
+    SierraLite,
+    JarvisJudiceNinke,
+    Stucki,
+    Burkes,
+    Sierra3,
+    TwoRowSierra,
+    Atkinson,
+    ShiauFan,
+    ShiauFan2,
+    /// Error diffusion with any kernel, see `DiffusionKernel`.
+    Custom(DiffusionKernel),
+    /// Ordered dithering with a size x size Bayer matrix, see `ordered::bayer_matrix`.
+    Bayer(usize),
+    /// Ordered dithering against a tiled blue noise mask, generated or user supplied.
+    BlueNoise,
+    /// Error carried along a Hilbert curve, see `riemersma::riemersma_quantize`.
+    Riemersma,
+    /// Knoll's pattern dithering, see `pattern::pattern_quantize`.
+    Knoll,
+    /// Yliluoma's pattern dithering, see `pattern::pattern_quantize`.
+    Yliluoma,
+}
+
+impl DitherMode {
+    /// The kernel an error diffusion mode spreads error with, `None` for the other modes.
+    pub fn diffusion_kernel(&self) -> Option<DiffusionKernel> {
+        match self {
+            DitherMode::FloydSteinberg => Some(DiffusionKernel::floyd_steinberg()),
+            DitherMode::SierraLite => Some(DiffusionKernel::sierra_lite()),
+            DitherMode::JarvisJudiceNinke => Some(DiffusionKernel::jarvis_judice_ninke()),
+            DitherMode::Stucki => Some(DiffusionKernel::stucki()),
+            DitherMode::Burkes => Some(DiffusionKernel::burkes()),
+            DitherMode::Sierra3 => Some(DiffusionKernel::sierra_3()),
+            DitherMode::TwoRowSierra => Some(DiffusionKernel::two_row_sierra()),
+            DitherMode::Atkinson => Some(DiffusionKernel::atkinson()),
+            DitherMode::ShiauFan => Some(DiffusionKernel::shiau_fan()),
+            DitherMode::ShiauFan2 => Some(DiffusionKernel::shiau_fan_2()),
+            DitherMode::Custom(kernel) => Some(kernel.clone()),
+            DitherMode::Base | DitherMode::Bayer(_) | DitherMode::BlueNoise | DitherMode::Riemersma | DitherMode::Knoll | DitherMode::Yliluoma => None,
+        }
+    }
+}
+
+/// Sobel magnitude, in channel units, at which no more error crosses a pixel: a clean step of 64.
+const EDGE_STOP: i32 = 256;
+
+/// How much of the error is spread, shared by every error diffusion mode.
+#[derive(Clone, Copy, Debug)]
+pub struct DiffusionControls {
+    strength: f32,
+    max_error: Option<u16>,
+    edge_aware: bool,
+    linear_light: bool,
+}
+
+impl Default for DiffusionControls {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DiffusionControls {
+    pub fn new() -> Self {
+        Self { strength: 1.0, max_error: None, edge_aware: false, linear_light: false }
+    }
+    /// Share of every error that is passed on, from 0 (none) to 1 (all of it). Defaults to 1.
+    pub fn strength(mut self, strength: f32) -> Self {
+        self.strength = strength;
+        self
+    }
+    /// Caps the error a pixel can take on, per channel in channel units, so it can't
+    /// snowball across flat areas. Defaults to no cap.
+    pub fn max_error(mut self, max_error: u16) -> Self {
+        self.max_error = Some(max_error);
+        self
+    }
+    /// Fade the error out across strong gradients, going by the Sobel magnitude of the source,
+    /// so hard edges in UI screenshots and pixel art stay clean. Defaults to `false`.
+    pub fn edge_aware(mut self, edge_aware: bool) -> Self {
+        self.edge_aware = edge_aware;
+        self
+    }
+    /// Spread the error in 12-bit linear light instead of the working space. Defaults to `false`.
+    pub fn linear_light(mut self, linear_light: bool) -> Self {
+        self.linear_light = linear_light;
+        self
+    }
+
+    // the incoming error of a pixel, capped.
+    fn limit(&self, error: Rgb<i16>) -> Rgb<i16> {
+        match self.max_error {
+            Some(max_error) => {
+                let max_error = if self.linear_light {
+                    (f32::from(max_error) * f32::from(LINEAR_LIGHT_MAX) / 255.0).round() as i16
+                } else {
+                    max_error.min(i16::MAX as u16) as i16
+                };
+                Rgb(error.0.map(|e| e.clamp(-max_error, max_error)))
+            },
+            None => error,
+        }
+    }
+
+    // the outgoing error of a pixel, scaled down by the strength.
+    fn scale(&self, error: [i32; 3]) -> [i32; 3] {
+        if self.strength >= 1.0 {
+            return error;
+        }
+        error.map(|e| (e as f32 * self.strength).round() as i32)
+    }
+
+    fn edges(&self, source: &RgbaImage) -> Option<EdgeWeights> {
+        self.edge_aware.then(|| EdgeWeights::new(source))
+    }
+}
+
+/// How much error may cross each pixel of `source`, from 0 to `EDGE_STOP`, worked out
+/// from the Sobel gradient once per pixel, since every kernel tap reads it again.
+struct EdgeWeights {
+    width: usize,
+    weights: Vec<u16>,
+}
+
+impl EdgeWeights {
+    fn new(source: &RgbaImage) -> Self {
+        let (width, height) = (source.width() as usize, source.height() as usize);
+        let raw = source.as_raw();
+        let channel = |x: usize, y: usize, c: usize| i32::from(raw[(y * width + x) * 4 + c]);
+        let mut weights = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let (up, down) = (y.saturating_sub(1), (y + 1).min(height - 1));
+            for x in 0..width {
+                let (left, right) = (x.saturating_sub(1), (x + 1).min(width - 1));
+                // the strongest channel, so edges between colors of the same brightness count too.
+                let magnitude = (0..3)
+                    .map(|c| {
+                        let gx = channel(right, up, c) + 2 * channel(right, y, c) + channel(right, down, c)
+                            - channel(left, up, c) - 2 * channel(left, y, c) - channel(left, down, c);
+                        let gy = channel(left, down, c) + 2 * channel(x, down, c) + channel(right, down, c)
+                            - channel(left, up, c) - 2 * channel(x, up, c) - channel(right, up, c);
+                        gx.abs() + gy.abs()
+                    })
+                    .max()
+                    .unwrap_or(0);
+                weights.push((EDGE_STOP - magnitude.min(EDGE_STOP)) as u16);
+            }
+        }
+
+        Self { width, weights }
+    }
+
+    // taps below the last row read the last row, their error is never used anyway.
+    fn weight(&self, x: usize, y: usize) -> i32 {
+        let y = y.min(self.weights.len() / self.width - 1);
+        i32::from(self.weights[y * self.width + x])
+    }
+}
+
+/// How `quantize_dither_image` spreads the quantization error.
+#[derive(Clone, Debug)]
+pub struct ErrorDiffusion {
+    kernel: DiffusionKernel,
+    serpentine: bool,
+    controls: DiffusionControls,
+}
+
+impl ErrorDiffusion {
+    pub fn new(kernel: DiffusionKernel) -> Self {
+        Self { kernel, serpentine: false, controls: DiffusionControls::new() }
+    }
+    /// Scan every other row right to left, mirroring the kernel, which breaks up
+    /// the diagonal worms a one way scan leaves behind. Defaults to `false`.
+    pub fn serpentine(mut self, serpentine: bool) -> Self {
+        self.serpentine = serpentine;
+        self
+    }
+    /// Strength, error cap, edge awareness and linear light. Defaults to `DiffusionControls::new()`.
+    pub fn controls(mut self, controls: DiffusionControls) -> Self {
+        self.controls = controls;
+        self
+    }
+}
+
+// error is kept in sRGB bytes, or in 12-bit linear light when `linear_light` is set.
+fn to_error_units(color: &Rgba<u8>, linear_light: bool) -> Rgb<i16> {
+    let [r, g, b, _] = color.0;
+    if linear_light {
+        Rgb([r, g, b].map(|c| to_linear_light(c) as i16))
+    } else {
+        Rgb([r, g, b].map(i16::from))
+    }
+}
+
+fn from_error_units(units: &Rgb<i16>, alpha: u8, linear_light: bool) -> Rgba<u8> {
+    let [r, g, b] = if linear_light {
+        units.0.map(|c| from_linear_light(c.max(0) as u16))
+    } else {
+        units.0.map(|c| c as u8)
+    };
+    Rgba([r, g, b, alpha])
+}
+
+// low hanging optimizations: 
+// - in place modification of rgb color.
+// alpha is never diffused, it passes through as is.
+// linear light keeps some headroom past black and white: shadows are squeezed into so few
+// values there that clamping at 0 throws away most of their error, and pushes them lighter.
+fn dither_apply_error(err_color: &Rgb<i16>, color: &Rgba<u8>, linear_light: bool) -> Rgb<i16> {
+    let (min, max) = if linear_light {
+        (-(LINEAR_LIGHT_MAX as i16), 2 * LINEAR_LIGHT_MAX as i16)
+    } else {
+        (0, u8::MAX.into())
+    };
+    let src = to_error_units(color, linear_light);
+
+    Rgb([0, 1, 2].map(|c| src.0[c].saturating_add(err_color.0[c]).clamp(min, max)))
+}
+
+/// The error still to be added to the rows a kernel reaches, one buffer per kernel row,
+/// recycled as the scan moves down, so memory grows with the width and not the image.
+/// Sums are kept in i32, which can't overflow however many taps pile onto a pixel.
+struct ErrorRows {
+    rows: VecDeque<Vec<[i32; 3]>>,
+}
+
+impl ErrorRows {
+    fn new(width: usize, kernel: &DiffusionKernel) -> Self {
+        Self { rows: (0..kernel.rows()).map(|_| vec![[0; 3]; width]).collect() }
+    }
+
+    fn width(&self) -> usize {
+        self.rows[0].len()
+    }
+
+    // the error waiting at `x` on the current row, saturated to what `dither_apply_error` takes.
+    fn take(&mut self, x: usize) -> Rgb<i16> {
+        let error = std::mem::take(&mut self.rows[0][x]);
+        Rgb(error.map(|e| e.clamp(i16::MIN.into(), i16::MAX.into()) as i16))
+    }
+
+    fn add(&mut self, x: usize, dy: usize, share: [i32; 3]) {
+        let error = &mut self.rows[dy][x];
+        *error = [0, 1, 2].map(|c| error[c] + share[c]);
+    }
+
+    // moves down a row: the current buffer is cleared and goes to the back.
+    fn next_row(&mut self) {
+        let mut row = self.rows.pop_front().expect("kernels reach at least one row");
+        row.fill([0; 3]);
+        self.rows.push_back(row);
+    }
+}
+
+// taps landing left or right of the image are dropped, along with their share of the error,
+// and so are taps below the last row, whose buffers are never read.
+// shares are cut from the running total, so rounding never loses error, even with divisors like 48.
+// `mirrored` flips the kernel left to right, for rows scanned backwards. with `edges`, every share
+// fades with the stronger gradient of the two pixels it goes between.
+fn diffuse_error(errors: &mut ErrorRows, (x, y): (usize, usize), error: [i32; 3], kernel: &DiffusionKernel, mirrored: bool, edges: Option<&EdgeWeights>) {
+    let width = errors.width();
+    let source_weight = edges.map(|edges| edges.weight(x, y));
+    // `DiffusionKernel::new` keeps the weights and the divisor within `i32`, and the running
+    // total within the divisor, so every spread fits back into an `i32`.
+    let divisor = i64::from(kernel.divisor());
+    let mut weight_so_far = 0;
+    let mut spread_so_far = [0; 3];
+
+    for tap in kernel.taps() {
+        weight_so_far += i64::from(tap.weight);
+        let spread = error.map(|e| (i64::from(e) * weight_so_far / divisor) as i32);
+        let share = [0, 1, 2].map(|c| spread[c] - spread_so_far[c]);
+        spread_so_far = spread;
+
+        let dx = if mirrored { -tap.dx } else { tap.dx };
+        let tap_x = x as i64 + i64::from(dx);
+        if tap_x < 0 || tap_x >= width as i64 {
+            continue;
+        }
+        let share = match (edges, source_weight) {
+            (Some(edges), Some(source_weight)) => {
+                let weight = source_weight.min(edges.weight(tap_x as usize, y + tap.dy as usize));
+                share.map(|e| e * weight / EDGE_STOP)
+            },
+            _ => share,
+        };
+        errors.add(tap_x as usize, tap.dy as usize, share);
+    }
+}
+
+/// Maps every pixel of `source` straight to its palette entry, without dithering.
+/// Fully transparent pixels go to `transparent_index` when there is one.
+///
+/// Returns one palette index per pixel, in row-major order.
+pub fn base_quantize(generator: &dyn PaletteGenerator, source: &RgbaImage, transparent_index: Option<u32>) -> Vec<u32> {
+    let mut indices = Vec::with_capacity(source.len() / 4);
+    for rgba in source.pixels() {
+        if let Some(transparent_index) = transparent_index && rgba.0[3] == 0 {
+            indices.push(transparent_index);
+            continue;
+        }
+        let palette_index = generator.get_palette_index(generator.normalize(*rgba), true).expect("PaletteGenerator on base palette_index couldn't find a color!");
+        indices.push(palette_index as u32);
+    }
+
+    indices
+}
+
+/// Maps every pixel of `source` to the palette, diffusing the quantization error
+/// as `diffusion` says. Fully transparent pixels go to
+/// `transparent_index` when there is one, swallowing their error instead of spreading it.
+/// `source` and `palette` are both in the quantizer's color space, which is where the error
+/// is spread, unless the controls spread it in linear light instead.
+/// `distance` picks the entry for colors the generator can't place.
+///
+/// Returns one palette index per pixel, in row-major order, whichever way the rows were scanned.
+pub fn quantize_dither_image(generator: &dyn PaletteGenerator, palette: &[Rgba<u8>], source: &RgbaImage, diffusion: &ErrorDiffusion, transparent_index: Option<u32>, distance: &dyn ColorDistance) -> Vec<u32> {
+    let ErrorDiffusion { kernel, serpentine, controls } = diffusion;
+    let linear_light = controls.linear_light;
+    let edges = controls.edges(source);
+    let image_width = source.width() as usize;
+    let mut errors = ErrorRows::new(image_width, kernel);
+    let mut indices = vec![0; image_width * source.height() as usize];
+
+    for y in 0..source.height() as usize {
+        let mirrored = *serpentine && y % 2 == 1;
+        for column in 0..image_width {
+            let x = if mirrored { image_width - 1 - column } else { column };
+            let index = (image_width * y) + x;
+            let rgba = source.get_pixel(x as u32, y as u32);
+            let dither_rgb = errors.take(x);
+            if let Some(transparent_index) = transparent_index && rgba.0[3] == 0 {
+                indices[index] = transparent_index;
+                continue;
+            }
+            let rgba = generator.normalize(*rgba);
+            // - apply error
+            let dither_rgb = controls.limit(dither_rgb);
+            let corrected = dither_apply_error(&dither_rgb, &rgba, linear_light);
+            let corrected_rgba = from_error_units(&corrected, rgba.0[3], linear_light);
+            // - get nearest color from palette
+            let palette_index = match generator.get_palette_index(corrected_rgba, true) {
+                Some(index) => index,
+                None => nearest_color_from_palette(palette, &rgba, distance),
+            };
+            let palette_color = to_error_units(&palette[palette_index], linear_light);
+            // - diffuse error
+            let error = controls.scale([0, 1, 2].map(|c| i32::from(corrected.0[c]) - i32::from(palette_color.0[c])));
+            diffuse_error(&mut errors, (x, y), error, kernel, mirrored, edges.as_ref());
+
+            indices[index] = palette_index as u32;
+        }
+        errors.next_row();
+    }
+
+    indices
+}
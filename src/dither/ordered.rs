@@ -0,0 +1,139 @@
+use image::{GrayImage, Rgba, RgbaImage};
+
+use crate::core::color_space::{from_linear_light, to_linear_light, LINEAR_LIGHT_MAX};
+use crate::core::palette_generator::PaletteGenerator;
+use crate::core::rgb_helpers::{nearest_color_from_palette, ColorDistance};
+use crate::dither::blue_noise::void_and_cluster;
+
+/// The dispersed 3x3 pattern the `3·2^k` sizes grow from.
+const BAYER_3: [u32; 9] = [
+    0, 7, 3,
+    6, 5, 2,
+    4, 1, 8,
+];
+
+/// Whether `bayer_matrix` can build a `size`x`size` matrix: powers of two and
+/// three times powers of two, from 2 to 256.
+pub fn is_bayer_size(size: usize) -> bool {
+    let base = if size.is_multiple_of(3) { size / 3 } else { size };
+    (2..=256).contains(&size) && base.is_power_of_two()
+}
+
+/// The `size`x`size` Bayer index matrix in row-major order, holding every value in `0..size²` once.
+///
+/// Each doubling tiles the previous matrix four times as `4M + [0 2; 3 1]`, so any
+/// two values that are close together land far apart. `None` when `is_bayer_size` says no.
+pub fn bayer_matrix(size: usize) -> Option<Vec<u32>> {
+    if !is_bayer_size(size) {
+        return None;
+    }
+    let (mut matrix, mut n) = if size.is_multiple_of(3) { (BAYER_3.to_vec(), 3) } else { (vec![0], 1) };
+    while n < size {
+        let mut next = vec![0; 4 * n * n];
+        for y in 0..2 * n {
+            for x in 0..2 * n {
+                let quadrant = [0, 2, 3, 1][(y / n) * 2 + x / n];
+                next[y * 2 * n + x] = 4 * matrix[(y % n) * n + x % n] + quadrant;
+            }
+        }
+        matrix = next;
+        n *= 2;
+    }
+
+    Some(matrix)
+}
+
+/// How far apart the palette colors sit: the mean euclidean distance from every entry
+/// to its nearest other entry, in channel units. A good default spread for ordered dithering,
+/// since a pixel can then be pushed onto either neighbouring color.
+pub fn palette_spread(palette: &[Rgba<u8>]) -> f32 {
+    if palette.len() < 2 {
+        return 0.0;
+    }
+    let total: f32 = palette.iter().enumerate()
+        .map(|(i, lhs)| {
+            palette.iter().enumerate()
+                .filter(|&(j, _)| i != j)
+                .map(|(_, rhs)| (0..3).map(|c| (f32::from(lhs.0[c]) - f32::from(rhs.0[c])).powi(2)).sum::<f32>())
+                .fold(f32::INFINITY, f32::min)
+                .sqrt()
+        })
+        .sum();
+
+    total / palette.len() as f32
+}
+
+/// A tiling matrix of per-pixel offsets, added to every channel before the palette lookup.
+pub struct ThresholdMap {
+    width: usize,
+    height: usize,
+    offsets: Vec<i16>,
+}
+
+impl ThresholdMap {
+    /// Maps `levels`, each in `0..level_count`, evenly onto `-spread / 2..spread / 2`.
+    /// `spread` is in channel units, and is scaled up to 12 bits when dithering in linear light.
+    pub fn from_levels(levels: &[u32], level_count: u32, width: usize, height: usize, spread: f32, linear_light: bool) -> Self {
+        assert_eq!(levels.len(), width * height, "threshold levels don't fill the matrix");
+        let spread = if linear_light { spread * f32::from(LINEAR_LIGHT_MAX) / 255.0 } else { spread };
+        let offsets = levels.iter()
+            .map(|&level| (((level as f32 + 0.5) / level_count as f32 - 0.5) * spread).round() as i16)
+            .collect();
+
+        Self { width, height, offsets }
+    }
+
+    /// A `size`x`size` Bayer matrix, see `bayer_matrix`.
+    pub fn bayer(size: usize, spread: f32, linear_light: bool) -> Option<Self> {
+        let matrix = bayer_matrix(size)?;
+        Some(Self::from_levels(&matrix, (size * size) as u32, size, size, spread, linear_light))
+    }
+
+    /// A `size`x`size` blue noise mask, see `void_and_cluster`.
+    pub fn blue_noise(size: usize, spread: f32, linear_light: bool) -> Self {
+        Self::from_levels(&void_and_cluster(size), (size * size) as u32, size, size, spread, linear_light)
+    }
+
+    /// Thresholds straight from the brightness of a grayscale mask, such as a blue noise texture.
+    pub fn from_mask(mask: &GrayImage, spread: f32, linear_light: bool) -> Self {
+        let levels: Vec<u32> = mask.pixels().map(|luma| u32::from(luma.0[0])).collect();
+        Self::from_levels(&levels, 256, mask.width() as usize, mask.height() as usize, spread, linear_light)
+    }
+
+    fn offset(&self, x: usize, y: usize) -> i16 {
+        self.offsets[(y % self.height) * self.width + x % self.width]
+    }
+}
+
+fn apply_threshold(rgba: &Rgba<u8>, offset: i16, linear_light: bool) -> Rgba<u8> {
+    let [r, g, b, a] = rgba.0;
+    let [r, g, b] = if linear_light {
+        [r, g, b].map(|c| from_linear_light((to_linear_light(c) as i16 + offset).clamp(0, LINEAR_LIGHT_MAX as i16) as u16))
+    } else {
+        [r, g, b].map(|c| (i16::from(c) + offset).clamp(0, u8::MAX.into()) as u8)
+    };
+    Rgba([r, g, b, a])
+}
+
+/// Maps every pixel of `source` to the palette after offsetting it by its cell in `thresholds`.
+/// Every pixel only depends on its own position, so the pattern is stable between frames and
+/// rows can be mapped in any order. Fully transparent pixels go to `transparent_index` when there is one.
+/// `distance` picks the entry for colors the generator can't place.
+///
+/// Returns one palette index per pixel, in row-major order.
+pub fn ordered_quantize(generator: &dyn PaletteGenerator, palette: &[Rgba<u8>], source: &RgbaImage, thresholds: &ThresholdMap, transparent_index: Option<u32>, distance: &dyn ColorDistance, linear_light: bool) -> Vec<u32> {
+    source.enumerate_pixels()
+        .map(|(x, y, rgba)| {
+            if let Some(transparent_index) = transparent_index && rgba.0[3] == 0 {
+                return transparent_index;
+            }
+            let rgba = generator.normalize(*rgba);
+            let offset_rgba = apply_threshold(&rgba, thresholds.offset(x as usize, y as usize), linear_light);
+            let palette_index = match generator.get_palette_index(offset_rgba, true) {
+                Some(index) => index,
+                None => nearest_color_from_palette(palette, &offset_rgba, distance),
+            };
+            palette_index as u32
+        })
+        .collect()
+}
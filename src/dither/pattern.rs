@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+
+use image::{Rgb, Rgba, RgbaImage};
+
+use crate::core::color_space::{ColorSpace, LINEAR_LIGHT_MAX};
+use crate::core::palette_generator::PaletteGenerator;
+use crate::core::rgb_helpers::{nearest_color_from_palette, ColorDistance};
+use crate::dither::ordered::bayer_matrix;
+use crate::dither::{from_error_units, to_error_units};
+
+/// Side of the Bayer matrix that picks a pixel's entry out of its plan.
+const MATRIX_SIZE: usize = 8;
+/// Palette entries in every plan, one per matrix cell.
+const PLAN_SIZE: usize = MATRIX_SIZE * MATRIX_SIZE;
+/// Colors that only differ in these low bits of every channel share a Yliluoma plan.
+const KEY_MASK: u8 = 0b1111_1100;
+/// The middle of the low bits `KEY_MASK` drops, which stands in for every color of a bucket.
+const KEY_CENTER: u8 = (!KEY_MASK).div_ceil(2);
+/// Yliluoma's method only mixes this many of the entries nearest to the color.
+const YLILUOMA_CANDIDATES: usize = 16;
+
+/// How a color is broken down into a mix of palette entries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MixingMethod {
+    /// Thomas Knoll's: each entry is the one nearest to the color plus the error of the entries so far.
+    Knoll,
+    /// Joel Yliluoma's second algorithm: entries are added greedily, in doubling amounts,
+    /// whenever that brings the mean of the plan closer to the color.
+    Yliluoma,
+}
+
+/// How `pattern_quantize` breaks colors down into palette entries.
+#[derive(Clone, Copy, Debug)]
+pub struct PatternDither {
+    method: MixingMethod,
+    color_space: ColorSpace,
+    linear_light: bool,
+}
+
+impl PatternDither {
+    pub fn new(method: MixingMethod) -> Self {
+        Self { method, color_space: ColorSpace::Srgb, linear_light: false }
+    }
+    /// The space the palette and colors are in, decoded to sRGB to order plans by luma. Defaults to sRGB.
+    pub fn color_space(mut self, color_space: ColorSpace) -> Self {
+        self.color_space = color_space;
+        self
+    }
+    /// Mix in 12-bit linear light, which mixes the way the eye does. Defaults to `false`.
+    pub fn linear_light(mut self, linear_light: bool) -> Self {
+        self.linear_light = linear_light;
+        self
+    }
+}
+
+/// `PLAN_SIZE` palette indices whose mean comes close to `color`, sorted by luma
+/// so that neighbouring matrix cells get contrasting entries.
+///
+/// `color` and the mean are compared in error units, see `PatternDither::linear_light`.
+/// `distance` judges which entry or mix is closest.
+pub fn mixing_plan(dither: &PatternDither, palette: &[Rgba<u8>], color: &Rgba<u8>, distance: &dyn ColorDistance) -> Vec<u32> {
+    let lumas = palette_lumas(palette, dither.color_space);
+    cached_mixing_plan(dither, palette, &lumas, color, distance, &mut HashMap::new())
+}
+
+// the sRGB luma of every entry, which is what makes neighbouring cells contrast on screen.
+fn palette_lumas(palette: &[Rgba<u8>], color_space: ColorSpace) -> Vec<u32> {
+    palette.iter()
+        .map(|rgba| {
+            let [r, g, b, _] = color_space.decode(*rgba).0.map(u32::from);
+            299 * r + 587 * g + 114 * b
+        })
+        .collect()
+}
+
+// `nearest` remembers the nearest entry of every color looked up so far, plans share many of them.
+fn cached_mixing_plan(dither: &PatternDither, palette: &[Rgba<u8>], lumas: &[u32], color: &Rgba<u8>, distance: &dyn ColorDistance, nearest: &mut HashMap<Rgba<u8>, usize>) -> Vec<u32> {
+    let mut plan = match dither.method {
+        MixingMethod::Knoll => knoll_plan(palette, color, distance, nearest, dither.linear_light),
+        MixingMethod::Yliluoma => yliluoma_plan(palette, color, distance, dither.linear_light),
+    };
+    plan.sort_by_key(|&index| lumas[index as usize]);
+
+    plan
+}
+
+fn knoll_plan(palette: &[Rgba<u8>], color: &Rgba<u8>, distance: &dyn ColorDistance, nearest: &mut HashMap<Rgba<u8>, usize>, linear_light: bool) -> Vec<u32> {
+    let target = to_error_units(color, linear_light).0.map(i32::from);
+    let mut error = [0i32; 3];
+    (0..PLAN_SIZE)
+        .map(|_| {
+            let attempt = clamp_units([0, 1, 2].map(|c| target[c] + error[c]), linear_light);
+            let attempt = from_error_units(&attempt, color.0[3], linear_light);
+            let index = *nearest.entry(attempt).or_insert_with(|| nearest_color_from_palette(palette, &attempt, distance));
+            let chosen = to_error_units(&palette[index], linear_light);
+            error = [0, 1, 2].map(|c| error[c] + target[c] - i32::from(chosen.0[c]));
+            index as u32
+        })
+        .collect()
+}
+
+// entries far from the color hardly ever bring the mean closer, so only the nearest few are tried.
+fn yliluoma_plan(palette: &[Rgba<u8>], color: &Rgba<u8>, distance: &dyn ColorDistance, linear_light: bool) -> Vec<u32> {
+    let mut candidates: Vec<usize> = (0..palette.len()).collect();
+    candidates.sort_by_cached_key(|&index| (distance.distance(&palette[index], color), index));
+    candidates.truncate(YLILUOMA_CANDIDATES);
+    let entries: Vec<[i32; 3]> = candidates.iter().map(|&index| to_error_units(&palette[index], linear_light).0.map(i32::from)).collect();
+    let mut plan = Vec::with_capacity(PLAN_SIZE);
+    let mut sum_so_far = [0i32; 3];
+    while plan.len() < PLAN_SIZE {
+        let count_so_far = plan.len() as i32;
+        let max_amount = plan.len().clamp(1, PLAN_SIZE - plan.len()) as i32;
+        let mut best: Option<(u32, usize, i32)> = None;
+        for (index, entry) in entries.iter().enumerate() {
+            let mut amount = 1;
+            while amount <= max_amount {
+                let count = count_so_far + amount;
+                let mean = clamp_units([0, 1, 2].map(|c| (sum_so_far[c] + entry[c] * amount + count / 2) / count), linear_light);
+                let penalty = distance.distance(color, &from_error_units(&mean, color.0[3], linear_light));
+                if best.is_none_or(|(least, _, _)| penalty < least) {
+                    best = Some((penalty, index, amount));
+                }
+                amount *= 2;
+            }
+        }
+        let (_, candidate, amount) = best.expect("the palette has at least one entry");
+        sum_so_far = [0, 1, 2].map(|c| sum_so_far[c] + entries[candidate][c] * amount);
+        plan.extend(std::iter::repeat_n(candidates[candidate] as u32, amount as usize));
+    }
+
+    plan
+}
+
+fn clamp_units(units: [i32; 3], linear_light: bool) -> Rgb<i16> {
+    let max = if linear_light { i32::from(LINEAR_LIGHT_MAX) } else { u8::MAX.into() };
+    Rgb(units.map(|c| c.clamp(0, max) as i16))
+}
+
+/// Maps every pixel of `source` to one entry of its mixing plan, see `mixing_plan`,
+/// picked by the pixel's cell in an 8x8 Bayer matrix. Unlike threshold offsets, the plan
+/// only ever mixes entries around the color, so it holds up with irregular palettes.
+///
+/// Plans are cached per color, which keeps photos with many colors affordable. Yliluoma's
+/// method measures so many mixes that its plans are shared by colors that only differ in
+/// the two lowest bits of every channel. The shared plan is built from the middle of that
+/// bucket rather than from whichever color came first, so a pixel's result never depends
+/// on the rest of the image.
+/// Fully transparent pixels go to `transparent_index` when there is one, and `palette`
+/// should then leave the transparent slot out.
+///
+/// Returns one palette index per pixel, in row-major order.
+pub fn pattern_quantize(generator: &dyn PaletteGenerator, palette: &[Rgba<u8>], source: &RgbaImage, dither: &PatternDither, transparent_index: Option<u32>, distance: &dyn ColorDistance) -> Vec<u32> {
+    let matrix = bayer_matrix(MATRIX_SIZE).expect("8 is a bayer size");
+    let lumas = palette_lumas(palette, dither.color_space);
+    let mut plans: HashMap<Rgba<u8>, Vec<u32>> = HashMap::new();
+    let mut nearest = HashMap::new();
+    source.enumerate_pixels()
+        .map(|(x, y, rgba)| {
+            if let Some(transparent_index) = transparent_index && rgba.0[3] == 0 {
+                return transparent_index;
+            }
+            let rgba = generator.normalize(*rgba);
+            let [r, g, b, a] = rgba.0;
+            let key = match dither.method {
+                MixingMethod::Knoll => rgba,
+                MixingMethod::Yliluoma => Rgba([(r & KEY_MASK) | KEY_CENTER, (g & KEY_MASK) | KEY_CENTER, (b & KEY_MASK) | KEY_CENTER, a]),
+            };
+            let plan = plans.entry(key).or_insert_with(|| cached_mixing_plan(dither, palette, &lumas, &key, distance, &mut nearest));
+            let rank = matrix[(y as usize % MATRIX_SIZE) * MATRIX_SIZE + x as usize % MATRIX_SIZE];
+            plan[rank as usize]
+        })
+        .collect()
+}
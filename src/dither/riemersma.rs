@@ -0,0 +1,130 @@
+use std::sync::LazyLock;
+
+use image::{Rgb, Rgba, RgbaImage};
+
+use crate::core::palette_generator::PaletteGenerator;
+use crate::core::rgb_helpers::{nearest_color_from_palette, ColorDistance};
+use crate::dither::{dither_apply_error, from_error_units, to_error_units, DiffusionControls, EDGE_STOP};
+
+/// How many past errors are remembered.
+const HISTORY_SIZE: usize = 16;
+/// The newest error weighs this many times as much as the oldest one.
+const WEIGHT_RATIO: f32 = 16.0;
+
+/// Weights from oldest to newest, growing exponentially from 1 to `WEIGHT_RATIO`.
+static WEIGHTS: LazyLock<[i32; HISTORY_SIZE]> = LazyLock::new(|| {
+    let step = WEIGHT_RATIO.ln() / (HISTORY_SIZE - 1) as f32;
+    std::array::from_fn(|i| (step * i as f32).exp().round() as i32)
+});
+static WEIGHT_TOTAL: LazyLock<i32> = LazyLock::new(|| WEIGHTS.iter().sum());
+
+/// Visits every pixel of a `width`x`height` rectangle once, along Jakub Červený's generalized
+/// Hilbert curve, so neighbouring steps are always neighbouring pixels. Any size works,
+/// with a single diagonal step when a side is odd where a power of two wouldn't need one.
+pub fn hilbert_curve(width: u32, height: u32, mut visit: impl FnMut(u32, u32)) {
+    let (width, height) = (i64::from(width), i64::from(height));
+    if width == 0 || height == 0 {
+        return;
+    }
+    let mut visit = |x: i64, y: i64| visit(x as u32, y as u32);
+    if width >= height {
+        hilbert_section(0, 0, width, 0, 0, height, &mut visit);
+    } else {
+        hilbert_section(0, 0, 0, height, width, 0, &mut visit);
+    }
+}
+
+// walks the rectangle at (x, y) spanned by the major axis (ax, ay) and the minor axis (bx, by).
+fn hilbert_section(x: i64, y: i64, ax: i64, ay: i64, bx: i64, by: i64, visit: &mut impl FnMut(i64, i64)) {
+    let width = (ax + ay).abs();
+    let height = (bx + by).abs();
+    let (dax, day) = (ax.signum(), ay.signum());
+    let (dbx, dby) = (bx.signum(), by.signum());
+
+    if height == 1 {
+        (0..width).for_each(|i| visit(x + i * dax, y + i * day));
+        return;
+    }
+    if width == 1 {
+        (0..height).for_each(|i| visit(x + i * dbx, y + i * dby));
+        return;
+    }
+
+    let (mut ax2, mut ay2) = (ax.div_euclid(2), ay.div_euclid(2));
+    let (mut bx2, mut by2) = (bx.div_euclid(2), by.div_euclid(2));
+    if 2 * width > 3 * height {
+        // long and thin: split along the major axis into two halves.
+        if (ax2 + ay2).abs() % 2 == 1 && width > 2 {
+            (ax2, ay2) = (ax2 + dax, ay2 + day);
+        }
+        hilbert_section(x, y, ax2, ay2, bx, by, visit);
+        hilbert_section(x + ax2, y + ay2, ax - ax2, ay - ay2, bx, by, visit);
+    } else {
+        // up the minor axis, across, and back down.
+        if (bx2 + by2).abs() % 2 == 1 && height > 2 {
+            (bx2, by2) = (bx2 + dbx, by2 + dby);
+        }
+        hilbert_section(x, y, bx2, by2, ax2, ay2, visit);
+        hilbert_section(x + bx2, y + by2, ax, ay, bx - bx2, by - by2, visit);
+        hilbert_section(x + (ax - dax) + (bx2 - dbx), y + (ay - day) + (by2 - dby), -bx2, -by2, -(ax - ax2), -(ay - ay2), visit);
+    }
+}
+
+/// Maps every pixel of `source` to the palette in Hilbert curve order, see `hilbert_curve`,
+/// correcting each one by a decaying sum of the last 16 quantization errors, after Thiadmer Riemersma.
+/// The curve has no preferred direction, so neither has the dither pattern.
+///
+/// Unlike the original, which measures errors against the source pixel and weighs them about
+/// five times over, the weights add up to one and each error is that of the corrected pixel.
+/// Every error is then spread exactly once, so grays close to black and white survive.
+/// Fully transparent pixels go to `transparent_index` when there is one, and remember no error.
+/// `controls` and `distance` work as in `quantize_dither_image`, edge awareness fading
+/// the error a pixel takes on by the gradient under it.
+///
+/// Returns one palette index per pixel, in row-major order.
+pub fn riemersma_quantize(generator: &dyn PaletteGenerator, palette: &[Rgba<u8>], source: &RgbaImage, transparent_index: Option<u32>, distance: &dyn ColorDistance, controls: &DiffusionControls) -> Vec<u32> {
+    let linear_light = controls.linear_light;
+    let edges = controls.edges(source);
+    let image_width = source.width() as usize;
+    let mut indices = vec![0; image_width * source.height() as usize];
+    // ring buffer, `oldest` is where the next error goes.
+    let mut history = [[0i32; 3]; HISTORY_SIZE];
+    let mut oldest = 0;
+
+    hilbert_curve(source.width(), source.height(), |x, y| {
+        let index = y as usize * image_width + x as usize;
+        let rgba = source.get_pixel(x, y);
+        if let Some(transparent_index) = transparent_index && rgba.0[3] == 0 {
+            history[oldest] = [0; 3];
+            oldest = (oldest + 1) % HISTORY_SIZE;
+            indices[index] = transparent_index;
+            return;
+        }
+        let rgba = generator.normalize(*rgba);
+        let weighted = (0..HISTORY_SIZE).fold([0i32; 3], |sum, age| {
+            let error = history[(oldest + age) % HISTORY_SIZE];
+            [0, 1, 2].map(|c| sum[c] + error[c] * WEIGHTS[age])
+        });
+        let weighted = match &edges {
+            Some(edges) => {
+                let weight = edges.weight(x as usize, y as usize);
+                weighted.map(|e| e * weight / EDGE_STOP)
+            },
+            None => weighted,
+        };
+        let error = controls.limit(Rgb(weighted.map(|e| (e / *WEIGHT_TOTAL) as i16)));
+        let corrected = dither_apply_error(&error, &rgba, linear_light);
+        let corrected_rgba = from_error_units(&corrected, rgba.0[3], linear_light);
+        let palette_index = match generator.get_palette_index(corrected_rgba, true) {
+            Some(index) => index,
+            None => nearest_color_from_palette(palette, &rgba, distance),
+        };
+
+        let palette_color = to_error_units(&palette[palette_index], linear_light);
+        history[oldest] = controls.scale([0, 1, 2].map(|c| i32::from(corrected.0[c]) - i32::from(palette_color.0[c])));
+        oldest = (oldest + 1) % HISTORY_SIZE;
+        indices[index] = palette_index as u32;
+    });
+
+    indices
+}
@@ -0,0 +1,68 @@
+use std::{borrow::Cow, io::Write, path::Path};
+
+use gif::{Encoder, Frame};
+
+use crate::export::{save_encoded, ExportError};
+use crate::quantizer::QuantizedImage;
+
+/// Pixels below this alpha become the transparent index, GIF has no partial transparency.
+const TRANSPARENT_THRESHOLD: u8 = 128;
+
+/// Writes `quantized` as a single frame GIF using its palette and indices as-is.
+///
+/// Pixels that come out transparent all share one transparent index. That is the first
+/// palette entry that is transparent itself, such as the quantizer's transparent slot,
+/// otherwise one extra index appended after the palette, so the palette may only hold 255 colors.
+pub fn write_indexed_gif<W: Write>(quantized: &QuantizedImage, writer: W) -> Result<(), ExportError> {
+    let width = u16::try_from(quantized.width).map_err(|_| ExportError::TooLarge(quantized.width, quantized.height))?;
+    let height = u16::try_from(quantized.height).map_err(|_| ExportError::TooLarge(quantized.width, quantized.height))?;
+
+    let is_transparent = |i: usize, index: u32| {
+        let a = quantized.alpha.as_ref().map_or(quantized.palette[index as usize].0[3], |alpha| alpha[i]);
+        a < TRANSPARENT_THRESHOLD
+    };
+    let has_transparency = quantized.indices.iter().enumerate().any(|(i, &index)| is_transparent(i, index));
+    let transparent_entry = quantized.palette.iter().position(|rgba| rgba.0[3] < TRANSPARENT_THRESHOLD);
+    let reserved_slots = usize::from(has_transparency && transparent_entry.is_none());
+    let color_count = quantized.palette.len() + reserved_slots;
+    if color_count > 256 {
+        return Err(ExportError::TooManyColors(color_count));
+    }
+
+    let mut global_palette: Vec<u8> = quantized.palette.iter()
+        .flat_map(|rgba| [rgba.0[0], rgba.0[1], rgba.0[2]])
+        .collect();
+    let (indices, transparent_index) = if has_transparency {
+        let transparent_index = match transparent_entry {
+            Some(entry) => entry as u8,
+            None => {
+                global_palette.extend_from_slice(&[0, 0, 0]);
+                quantized.palette.len() as u8
+            },
+        };
+        let indices = quantized.indices.iter()
+            .enumerate()
+            .map(|(i, &index)| if is_transparent(i, index) { transparent_index } else { index as u8 })
+            .collect();
+        (indices, Some(transparent_index))
+    } else {
+        (quantized.indices.iter().map(|&index| index as u8).collect(), None)
+    };
+
+    let mut encoder = Encoder::new(writer, width, height, &global_palette)?;
+    let frame = Frame {
+        width,
+        height,
+        buffer: Cow::Owned(indices),
+        transparent: transparent_index,
+        ..Frame::default()
+    };
+    encoder.write_frame(&frame)?;
+
+    Ok(())
+}
+
+/// Writes `quantized` to `path`, see `write_indexed_gif`.
+pub fn save_indexed_gif<P: AsRef<Path>>(quantized: &QuantizedImage, path: P) -> Result<(), ExportError> {
+    save_encoded(path, |buffer| write_indexed_gif(quantized, buffer))
+}
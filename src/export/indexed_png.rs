@@ -0,0 +1,67 @@
+use std::{io::Write, path::Path};
+
+use png::{BitDepth, ColorType, Encoder};
+
+use crate::export::{save_encoded, ExportError, IndexedPalette};
+use crate::quantizer::QuantizedImage;
+
+/// Smallest PNG bit depth that can address every palette entry.
+fn bit_depth_for(palette_len: usize) -> BitDepth {
+    match palette_len {
+        0..=2 => BitDepth::One,
+        3..=4 => BitDepth::Two,
+        5..=16 => BitDepth::Four,
+        _ => BitDepth::Eight,
+    }
+}
+
+/// Packs one index per pixel into `bits`-wide samples, MSB first, padding every row to a whole byte.
+fn pack_rows(indices: &[u8], width: usize, bits: usize) -> Vec<u8> {
+    if bits == 8 {
+        return indices.to_vec();
+    }
+    let pixels_per_byte = 8 / bits;
+    let row_bytes = width.div_ceil(pixels_per_byte);
+    let mut packed = Vec::with_capacity(row_bytes * (indices.len() / width.max(1)));
+
+    for row in indices.chunks(width) {
+        for pixels in row.chunks(pixels_per_byte) {
+            let mut byte = 0u8;
+            for (i, &index) in pixels.iter().enumerate() {
+                byte |= index << (8 - bits * (i + 1));
+            }
+            packed.push(byte);
+        }
+    }
+
+    packed
+}
+
+/// Writes `quantized` as a palette PNG, with a tRNS chunk when the source had alpha.
+///
+/// Uses a 1, 2 or 4-bit depth when the palette is small enough, 8-bit otherwise.
+pub fn write_indexed_png<W: Write>(quantized: &QuantizedImage, writer: W) -> Result<(), ExportError> {
+    let IndexedPalette { colors, indices } = IndexedPalette::new(quantized)?;
+    let bit_depth = bit_depth_for(colors.len());
+
+    let mut encoder = Encoder::new(writer, quantized.width, quantized.height);
+    encoder.set_color(ColorType::Indexed);
+    encoder.set_depth(bit_depth);
+    encoder.set_palette(colors.iter().flat_map(|rgba| [rgba.0[0], rgba.0[1], rgba.0[2]]).collect::<Vec<u8>>());
+
+    let trns_len = colors.iter().rposition(|rgba| rgba.0[3] != u8::MAX).map_or(0, |i| i + 1);
+    if trns_len > 0 {
+        encoder.set_trns(colors[..trns_len].iter().map(|rgba| rgba.0[3]).collect::<Vec<u8>>());
+    }
+
+    let mut png_writer = encoder.write_header()?;
+    png_writer.write_image_data(&pack_rows(&indices, quantized.width as usize, bit_depth as usize))?;
+    png_writer.finish()?;
+
+    Ok(())
+}
+
+/// Writes `quantized` to `path`, see `write_indexed_png`.
+pub fn save_indexed_png<P: AsRef<Path>>(quantized: &QuantizedImage, path: P) -> Result<(), ExportError> {
+    save_encoded(path, |buffer| write_indexed_png(quantized, buffer))
+}
@@ -0,0 +1,95 @@
+pub mod indexed_gif;
+pub mod indexed_png;
+
+use std::{collections::HashMap, fs, path::Path};
+
+use image::Rgba;
+use thiserror::Error;
+
+use crate::quantizer::QuantizedImage;
+
+#[derive(Error, Debug)]
+pub enum ExportError {
+    #[error("Indexed images hold at most 256 colors, but this one needs {0}. Quantize with fewer colors.")]
+    TooManyColors(usize),
+    #[error("GIF dimensions are limited to 65535, but the image is {0}x{1}.")]
+    TooLarge(u32, u32),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Png(#[from] png::EncodingError),
+    #[error(transparent)]
+    Gif(#[from] gif::EncodingError),
+}
+
+/// Encodes into memory with `encode` and only then writes `path`, so a failed export
+/// never leaves a truncated file behind.
+pub(crate) fn save_encoded<P: AsRef<Path>>(path: P, encode: impl FnOnce(&mut Vec<u8>) -> Result<(), ExportError>) -> Result<(), ExportError> {
+    let mut buffer = Vec::new();
+    encode(&mut buffer)?;
+    fs::write(path, buffer)?;
+
+    Ok(())
+}
+
+/// The palette and indices that actually get written to disk.
+///
+/// An RGBA palette is written as is. When the source alpha was carried per pixel instead,
+/// every distinct (palette index, alpha) pair becomes its own file palette entry,
+/// translucent entries first so the tRNS chunk can stop at the last of them.
+pub(crate) struct IndexedPalette {
+    pub colors: Vec<Rgba<u8>>,
+    pub indices: Vec<u8>,
+}
+
+/// Whether `quantized` can be written as an indexed image, with every (palette index, alpha)
+/// pair counted when the source alpha was carried per pixel.
+pub fn fits_indexed(quantized: &QuantizedImage) -> bool {
+    match &quantized.alpha {
+        Some(alpha) => alpha_entries(quantized, alpha).len() <= 256,
+        None => quantized.palette.len() <= 256,
+    }
+}
+
+// every distinct (palette index, alpha) pair, translucent ones first.
+fn alpha_entries(quantized: &QuantizedImage, alpha: &[u8]) -> Vec<(u32, u8)> {
+    let mut entries: Vec<(u32, u8)> = quantized.indices.iter().copied().zip(alpha.iter().copied()).collect();
+    entries.sort_unstable_by_key(|&(index, a)| (a == u8::MAX, index, a));
+    entries.dedup();
+    entries
+}
+
+impl IndexedPalette {
+    pub fn new(quantized: &QuantizedImage) -> Result<Self, ExportError> {
+        let Some(alpha) = &quantized.alpha else {
+            if quantized.palette.len() > 256 {
+                return Err(ExportError::TooManyColors(quantized.palette.len()));
+            }
+            let colors = quantized.palette.clone();
+            let indices = quantized.indices.iter().map(|&i| i as u8).collect();
+            return Ok(Self { colors, indices });
+        };
+
+        let entries = alpha_entries(quantized, alpha);
+        if entries.len() > 256 {
+            return Err(ExportError::TooManyColors(entries.len()));
+        }
+
+        let entry_lookup: HashMap<(u32, u8), u8> = entries.iter()
+            .enumerate()
+            .map(|(i, &entry)| (entry, i as u8))
+            .collect();
+        let colors = entries.iter()
+            .map(|&(index, a)| {
+                let [r, g, b, _] = quantized.palette[index as usize].0;
+                Rgba([r, g, b, a])
+            })
+            .collect();
+        let indices = quantized.indices.iter()
+            .zip(alpha.iter())
+            .map(|(&index, &a)| entry_lookup[&(index, a)])
+            .collect();
+
+        Ok(Self { colors, indices })
+    }
+}
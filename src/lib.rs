@@ -0,0 +1,8 @@
+pub mod core;
+pub mod dither;
+pub mod export;
+pub mod morton;
+pub mod quantizer;
+
+pub use dither::DitherMode;
+pub use quantizer::{Algorithm, ColorSpace, DistanceMetric, QuantizeError, QuantizedImage, Quantizer};
@@ -1,141 +1,13 @@
-
-pub mod core;
-pub mod morton;
-
-use image::{ColorType, DynamicImage, GenericImage, GenericImageView, Pixel, Rgb, Rgba};
-use std::{env, path::{self, Path, PathBuf}, time::Instant, u32, u8};
+use image::{ColorType, DynamicImage, GenericImageView, Rgba};
+use std::{env, fs, path::{self, Path, PathBuf}, time::Instant};
 use getargs::{Arg, Options};
 use thiserror::Error;
 
-use core::rgb_helpers::{add_colors, color_diff};
-use core::accum_octree::LeafOctree;
-
-enum DitherMode {
-    Base,
-    FloydSteinberg,
-    SierraLite,
-}
-
-// low hanging optimizations: 
-// - in place modification of rgb color.
-fn dither_apply_error(err_color: &Rgb<i16>, color: &Rgb<u8>) -> Rgb<u8> {
-    let [err_r, err_g, err_b] = err_color.0;
-    let [src_r, src_g, src_b] = color.0.map(|c| i16::from(c));
-
-    let r = src_r + err_r;
-    let g = src_g + err_g;
-    let b = src_b + err_b;
-
-    let dest_r = r.max(0).min(u8::MAX.into()) as u8;
-    let dest_g = g.max(0).min(u8::MAX.into()) as u8;
-    let dest_b = b.max(0).min(u8::MAX.into()) as u8;
-
-    let rgb = Rgb([dest_r, dest_g, dest_b]);
-
-    rgb
-}
-
-// expensive function to account for the octree not covering all colors!
-// dithering makes new colors out of nowhere due to errors + original color = new color
-fn nearest_color_from_palette(palette: &Vec<Rgb<u8>>, rgb: &Rgb<u8>) -> usize {
-    let mut smallest_diff = u32::MAX;
-    let mut best_index: usize = 0;
-    for (i, palette_rgb) in palette.iter().enumerate() {
-        let diff = color_diff(palette_rgb, rgb);
-        if diff < 10 {
-            return i;
-        }
-        if diff < smallest_diff {
-            smallest_diff = diff;
-            best_index = i;
-        }
-    }
-
-    best_index
-}
-
-fn diffuse_pixel_sierra_lite(error_vec: &mut Vec<Rgb<i16>>, r_error: i16, g_error: i16, b_error: i16, error_index: usize, next_row_error_index: usize) {
-    let next_row_error_index = next_row_error_index.max(0).min(error_vec.len() - 1);
-    let front_curr_row = (error_index + 1).max(0).min(error_vec.len() - 1);
-    let front_next_row = (next_row_error_index + 1).max(0).min(error_vec.len() - 1);
-
-    add_colors(&mut error_vec[front_curr_row], &Rgb::<i16>([r_error * 2 / 4, g_error * 2 / 4, b_error * 2 / 4]));
-    add_colors(&mut error_vec[front_next_row], &Rgb::<i16>([r_error / 4, g_error / 4, b_error / 4]));
-    add_colors(&mut error_vec[next_row_error_index], &Rgb::<i16>([r_error / 4, g_error / 4, b_error / 4]));
-}
-fn diffuse_pixel_floyd_steinberg(error_vec: &mut Vec<Rgb<i16>>, r_error: i16, g_error: i16, b_error: i16, error_index: usize, next_row_error_index: usize) {
-    let front_curr_row = (error_index + 1).max(0).min(error_vec.len() - 1);
-    let behind_next_row = (next_row_error_index - 1).max(0).min(error_vec.len() - 1);
-    let next_row_error_index = next_row_error_index.max(0).min(error_vec.len() - 1);
-    let front_next_row = (next_row_error_index + 1).max(0).min(error_vec.len() - 1);
-
-    add_colors(&mut error_vec[front_curr_row], &Rgb::<i16>([r_error * 7 / 16, g_error * 7 / 16, b_error * 7 / 16]));
-    add_colors(&mut error_vec[front_next_row], &Rgb::<i16>([r_error / 16, g_error / 16, b_error / 16]));
-    add_colors(&mut error_vec[next_row_error_index], &Rgb::<i16>([r_error * 5 / 16, g_error * 5 / 16, b_error * 5 / 16]));
-    add_colors(&mut error_vec[behind_next_row], &Rgb::<i16>([r_error * 3 / 16, g_error * 3 / 16, b_error * 3 / 16]));
-}
-
-// pre-allocated error_vec.
-fn diffuse_error(error_vec: &mut Vec<Rgb<i16>>, width: usize, x: usize, y: usize, src_color: &Rgb<u8>, corrected_color: &Rgb<u8>, dither_mode: &DitherMode) {
-    let [src_r, src_g, src_b] = src_color.0;
-    let [corr_r, corr_g, corr_b] = corrected_color.0;
-    let error_index = (width * y) + x;
-    let next_row_error_index = (width * (y + 1)) + x;
-
-    let r_error = src_r as i16 - corr_r as i16;
-    let g_error = src_g as i16 - corr_g as i16;
-    let b_error = src_b as i16 - corr_b as i16;
-
-    match dither_mode {
-        DitherMode::SierraLite => diffuse_pixel_sierra_lite(error_vec, r_error, g_error, b_error, error_index, next_row_error_index),
-        DitherMode::FloydSteinberg => diffuse_pixel_floyd_steinberg(error_vec, r_error, g_error, b_error, error_index, next_row_error_index),
-        DitherMode::Base => panic!("base!!"),
-    }
-
-    error_vec[error_index] = Rgb([0, 0, 0]);
-}
-
-fn base_quantize(octree: &LeafOctree, palette: &Vec<Rgb<u8>>, source: &DynamicImage, destination: &mut DynamicImage) {
-    let (width, height) = source.dimensions();
-    for y in 0..height {
-        for x in 0..width {
-            let rgba = source.get_pixel(x, y);
-            let rgb = rgba.to_rgb();
-            let palette_index = octree.get_palette_index(rgb, true).expect("LeafOctree on dither palette_index couldn't find a color!");
-            let palette_color = palette[palette_index];
-
-            destination.put_pixel(x as u32, y as u32, Rgba([palette_color.0[0], palette_color.0[1], palette_color.0[2], rgba.0[3]]));
-        }
-    }
-}
-
-fn quantize_dither_image(octree: &LeafOctree, palette: &Vec<Rgb<u8>>, source: &DynamicImage, destination: &mut DynamicImage, dither_mode: &DitherMode) {
-    let image_width = source.width() as usize;
-    let mut error_vec = vec![Rgb::<i16>([0, 0, 0]); (source.width() * source.height()) as usize];
-
-    let (width, height) = source.dimensions();
-    for y in 0..height {
-        for x in 0..width {
-            let rgba = source.get_pixel(x, y);
-            let rgb = rgba.to_rgb();
-            // - apply error
-            let error_index = (image_width * y as usize) + x as usize;
-            let dither_rgb = error_vec[error_index];
-            let corrected_rgb = dither_apply_error(&dither_rgb, &rgb);
-            // - get nearest color from palette
-            let palette_index = match octree.get_palette_index(corrected_rgb, true) {
-                Some(index) => index,
-                None => nearest_color_from_palette(palette, &rgb),
-            };
-            let palette_color = palette[palette_index];
-            // let palette_index = nearest_color_from_palette(palette, &corrected_rgb);
-            // - diffuse error
-            diffuse_error(&mut error_vec, image_width, x as usize, y as usize, &corrected_rgb, &palette_color, &dither_mode);
-
-            destination.put_pixel(x as u32, y as u32, Rgba([palette_color.0[0], palette_color.0[1], palette_color.0[2], rgba.0[3]]));
-        }
-    }
-}
+use imgquant::{Algorithm, ColorSpace, DistanceMetric, DitherMode, Quantizer};
+use imgquant::dither::kernel::DiffusionKernel;
+use imgquant::export::fits_indexed;
+use imgquant::export::indexed_gif::save_indexed_gif;
+use imgquant::export::indexed_png::save_indexed_png;
 
 fn add_to_filename(path: &Path, addition: &str) -> PathBuf {
     let parent = path.parent().unwrap_or_else(|| Path::new(""));
@@ -147,25 +19,43 @@ fn add_to_filename(path: &Path, addition: &str) -> PathBuf {
 }
 
 
-fn print_palette(palette: &Vec<Rgb<u8>>) {
+fn print_palette(palette: &[Rgba<u8>]) {
     print!("Palette: ");
-    for rgb in palette.iter() {
-        print_color_box(rgb);
+    for rgba in palette.iter() {
+        print_color_box(rgba);
         print!("\x1B[0m");
     }
     println!("\x1B[0m");
 }
 
-fn print_color_box(rgb: &Rgb<u8>) {
-    let [r, g, b] = rgb.0;
+fn print_color_box(rgba: &Rgba<u8>) {
+    let [r, g, b, _] = rgba.0;
     print!("\x1B[48;2;{};{};{}m ", r, g, b);
 }
 
 struct ParsedOptions {
     source_path: Box<Path>,
+    dest_path: Option<Box<Path>>,
+    algorithm: Algorithm,
+    color_space: ColorSpace,
+    distance_metric: DistanceMetric,
     color_size: i32,
     dither_mode: DitherMode,
     depth: usize,
+    with_alpha: bool,
+    transparent_slot: bool,
+    refine_iterations: usize,
+    refine_threshold: u32,
+    sample_factor: usize,
+    linear_light: bool,
+    ordered_spread: Option<f32>,
+    blue_noise_size: usize,
+    noise_mask_path: Option<Box<Path>>,
+    serpentine: bool,
+    dither_strength: f32,
+    max_dither_error: Option<u16>,
+    edge_aware: bool,
+    threads: usize,
 }
 
 #[derive(Error, Debug)]
@@ -184,9 +74,27 @@ fn parse_cli() -> Result<ParsedOptions, ParseErrors> {
     let args: Vec<String> = env::args().skip(1).collect();
     let mut opts = Options::new(args.iter().map(String::as_str));
     let mut source_path: Option<Box<Path>> = None;
+    let mut dest_path: Option<Box<Path>> = None;
+    let mut algorithm = Algorithm::Octree;
+    let mut color_space = ColorSpace::Srgb;
+    let mut distance_metric = DistanceMetric::WeightedRgb;
     let mut color_size = 256;
     let mut dither_mode = DitherMode::FloydSteinberg;
     let mut depth: usize = 6;
+    let mut with_alpha = false;
+    let mut transparent_slot = false;
+    let mut refine_iterations: usize = 0;
+    let mut refine_threshold: u32 = 8;
+    let mut sample_factor: usize = 10;
+    let mut linear_light = false;
+    let mut ordered_spread: Option<f32> = None;
+    let mut blue_noise_size: usize = 64;
+    let mut noise_mask_path: Option<Box<Path>> = None;
+    let mut serpentine = false;
+    let mut dither_strength: f32 = 1.0;
+    let mut max_dither_error: Option<u16> = None;
+    let mut edge_aware = false;
+    let mut threads: usize = 1;
     let mut option_count = 0;
 
     while let Some(arg) = opts.next_arg().expect("Parsing error.") {
@@ -201,11 +109,195 @@ fn parse_cli() -> Result<ParsedOptions, ParseErrors> {
                         "base" => dither_mode = DitherMode::Base,
                         "sierralite" | "sl" => dither_mode = DitherMode::SierraLite,
                         "floydsteinberg" | "fs" => dither_mode = DitherMode::FloydSteinberg,
-                        _ => return Err(ParseErrors::InvalidArgument(format!("{} is not a valid dither mode. Options: base, sierralite, floydsteinberg", s))),
+                        "jarvisjudiceninke" | "jjn" => dither_mode = DitherMode::JarvisJudiceNinke,
+                        "stucki" => dither_mode = DitherMode::Stucki,
+                        "burkes" => dither_mode = DitherMode::Burkes,
+                        "sierra3" | "sierra" => dither_mode = DitherMode::Sierra3,
+                        "tworowsierra" | "sierra2" => dither_mode = DitherMode::TwoRowSierra,
+                        "atkinson" => dither_mode = DitherMode::Atkinson,
+                        "shiaufan" => dither_mode = DitherMode::ShiauFan,
+                        "shiaufan2" => dither_mode = DitherMode::ShiauFan2,
+                        "bayer" => dither_mode = DitherMode::Bayer(8),
+                        "bluenoise" | "bn" => dither_mode = DitherMode::BlueNoise,
+                        "riemersma" => dither_mode = DitherMode::Riemersma,
+                        "knoll" => dither_mode = DitherMode::Knoll,
+                        "yliluoma" => dither_mode = DitherMode::Yliluoma,
+                        mode => match mode.strip_prefix("bayer").map(str::parse::<usize>) {
+                            Some(Ok(size)) => dither_mode = DitherMode::Bayer(size),
+                            _ => return Err(ParseErrors::InvalidArgument(format!("{} is not a valid dither mode. Options: base, sierralite, floydsteinberg, jjn, stucki, burkes, sierra3, tworowsierra, atkinson, shiaufan, shiaufan2, bayer, bayer<size>, bluenoise, riemersma, knoll, yliluoma", s))),
+                        },
                     }
                     Err(_) => return Err(ParseErrors::MissingArgument("dither".to_string()))
                 };
             }
+            Arg::Long("kernel") => {
+                let opt = opts.value();
+                match opt {
+                    Ok(s) => match s.parse::<DiffusionKernel>() {
+                        Ok(kernel) => dither_mode = DitherMode::Custom(kernel),
+                        Err(err) => return Err(ParseErrors::InvalidArgument(err.to_string())),
+                    },
+                    Err(_) => return Err(ParseErrors::MissingArgument("kernel".to_string()))
+                };
+            }
+            Arg::Long("kernel-file") => {
+                let opt = opts.value();
+                match opt {
+                    Ok(s) => match fs::read_to_string(s).map(|spec| spec.parse::<DiffusionKernel>()) {
+                        Ok(Ok(kernel)) => dither_mode = DitherMode::Custom(kernel),
+                        Ok(Err(err)) => return Err(ParseErrors::InvalidArgument(format!("{}: {}", s, err))),
+                        Err(err) => return Err(ParseErrors::InvalidArgument(format!("{}: {}", s, err))),
+                    },
+                    Err(_) => return Err(ParseErrors::MissingArgument("kernel-file".to_string()))
+                };
+            }
+            Arg::Long("algorithm") => {
+                let opt = opts.value();
+                match opt {
+                    Ok(s) => match s.to_lowercase().as_str() {
+                        "octree" => algorithm = Algorithm::Octree,
+                        "median-cut" | "mediancut" | "mc" => algorithm = Algorithm::MedianCut,
+                        "wu" => algorithm = Algorithm::Wu,
+                        "neuquant" | "nq" => algorithm = Algorithm::NeuQuant,
+                        "morton" => algorithm = Algorithm::Morton,
+                        _ => return Err(ParseErrors::InvalidArgument(format!("{} is not a valid algorithm. Options: octree, median-cut, wu, neuquant, morton", s))),
+                    }
+                    Err(_) => return Err(ParseErrors::MissingArgument("algorithm".to_string()))
+                };
+            }
+            Arg::Long("colorspace") => {
+                let opt = opts.value();
+                match opt {
+                    Ok(s) => match s.to_lowercase().as_str() {
+                        "srgb" => color_space = ColorSpace::Srgb,
+                        "linear" => color_space = ColorSpace::Linear,
+                        "lab" => color_space = ColorSpace::Lab,
+                        "oklab" => color_space = ColorSpace::Oklab,
+                        "yuv" => color_space = ColorSpace::Yuv,
+                        _ => return Err(ParseErrors::InvalidArgument(format!("{} is not a valid color space. Options: srgb, linear, lab, oklab, yuv", s))),
+                    }
+                    Err(_) => return Err(ParseErrors::MissingArgument("colorspace".to_string()))
+                };
+            }
+            Arg::Long("distance") => {
+                let opt = opts.value();
+                match opt {
+                    Ok(s) => match s.to_lowercase().as_str() {
+                        "rgb" => distance_metric = DistanceMetric::WeightedRgb,
+                        "lab" => distance_metric = DistanceMetric::EuclideanLab,
+                        "cie76" => distance_metric = DistanceMetric::Cie76,
+                        "cie94" => distance_metric = DistanceMetric::Cie94,
+                        "ciede2000" | "de2000" => distance_metric = DistanceMetric::Ciede2000,
+                        _ => return Err(ParseErrors::InvalidArgument(format!("{} is not a valid distance. Options: rgb, lab, cie76, cie94, ciede2000", s))),
+                    }
+                    Err(_) => return Err(ParseErrors::MissingArgument("distance".to_string()))
+                };
+            }
+            Arg::Long("alpha") => {
+                with_alpha = true;
+            }
+            Arg::Long("transparent") => {
+                transparent_slot = true;
+            }
+            Arg::Long("linear-light") => {
+                linear_light = true;
+            }
+            Arg::Long("serpentine") => {
+                serpentine = true;
+            }
+            Arg::Long("edge-aware") => {
+                edge_aware = true;
+            }
+            Arg::Long("dither-strength") => {
+                let opt = opts.value();
+                match opt {
+                    Ok(s) => match s.parse::<f32>() {
+                        Ok(n) => dither_strength = n,
+                        Err(_) => return Err(ParseErrors::InvalidArgument("Dither strength is not a number.".to_string())),
+                    },
+                    Err(_) => return Err(ParseErrors::MissingArgument("dither-strength".to_string()))
+                };
+            }
+            Arg::Long("error-clamp") => {
+                let opt = opts.value();
+                match opt {
+                    Ok(s) => match s.parse::<u16>() {
+                        Ok(n) => max_dither_error = Some(n),
+                        Err(_) => return Err(ParseErrors::InvalidArgument("Error clamp is not a number.".to_string())),
+                    },
+                    Err(_) => return Err(ParseErrors::MissingArgument("error-clamp".to_string()))
+                };
+            }
+            Arg::Long("refine-iterations") => {
+                let opt = opts.value();
+                match opt {
+                    Ok(s) => match s.parse::<usize>() {
+                        Ok(n) => refine_iterations = n,
+                        Err(_) => return Err(ParseErrors::InvalidArgument("Refine iterations is not a number.".to_string())),
+                    },
+                    Err(_) => return Err(ParseErrors::MissingArgument("refine-iterations".to_string()))
+                };
+            }
+            Arg::Long("refine-threshold") => {
+                let opt = opts.value();
+                match opt {
+                    Ok(s) => match s.parse::<u32>() {
+                        Ok(n) => refine_threshold = n,
+                        Err(_) => return Err(ParseErrors::InvalidArgument("Refine threshold is not a number.".to_string())),
+                    },
+                    Err(_) => return Err(ParseErrors::MissingArgument("refine-threshold".to_string()))
+                };
+            }
+            Arg::Long("spread") => {
+                let opt = opts.value();
+                match opt {
+                    Ok(s) => match s.parse::<f32>() {
+                        Ok(n) => ordered_spread = Some(n),
+                        Err(_) => return Err(ParseErrors::InvalidArgument("Spread is not a number.".to_string())),
+                    },
+                    Err(_) => return Err(ParseErrors::MissingArgument("spread".to_string()))
+                };
+            }
+            Arg::Long("noise-size") => {
+                let opt = opts.value();
+                match opt {
+                    Ok(s) => match s.parse::<usize>() {
+                        Ok(n) => blue_noise_size = n,
+                        Err(_) => return Err(ParseErrors::InvalidArgument("Noise size is not a number.".to_string())),
+                    },
+                    Err(_) => return Err(ParseErrors::MissingArgument("noise-size".to_string()))
+                };
+            }
+            Arg::Long("noise-mask") => {
+                let opt = opts.value();
+                match opt {
+                    Ok(s) => {
+                        let buf = PathBuf::from(s).into_boxed_path();
+                        noise_mask_path.replace(buf);
+                    },
+                    Err(_) => return Err(ParseErrors::MissingArgument("noise-mask".to_string()))
+                }
+            }
+            Arg::Long("sample-factor") => {
+                let opt = opts.value();
+                match opt {
+                    Ok(s) => match s.parse::<usize>() {
+                        Ok(n) => sample_factor = n,
+                        Err(_) => return Err(ParseErrors::InvalidArgument("Sample factor is not a number.".to_string())),
+                    },
+                    Err(_) => return Err(ParseErrors::MissingArgument("sample-factor".to_string()))
+                };
+            }
+            Arg::Long("threads") => {
+                let opt = opts.value();
+                match opt {
+                    Ok(s) => match s.parse::<usize>() {
+                        Ok(n) => threads = n,
+                        Err(_) => return Err(ParseErrors::InvalidArgument("Thread count is not a number.".to_string())),
+                    },
+                    Err(_) => return Err(ParseErrors::MissingArgument("threads".to_string()))
+                };
+            }
             Arg::Short('i') | Arg::Long("input") => {
                 let opt = opts.value();
                 match opt {
@@ -216,6 +308,16 @@ fn parse_cli() -> Result<ParsedOptions, ParseErrors> {
                     Err(_) => return Err(ParseErrors::MissingArgument("input".to_string()))
                 }
             }
+            Arg::Short('o') | Arg::Long("output") => {
+                let opt = opts.value();
+                match opt {
+                    Ok(s) => {
+                        let buf = PathBuf::from(s).into_boxed_path();
+                        dest_path.replace(buf);
+                    },
+                    Err(_) => return Err(ParseErrors::MissingArgument("output".to_string()))
+                }
+            }
             Arg::Short('d') | Arg::Long("depth") => { // unreachable.
                 let opt = opts.value();
                 match opt {
@@ -265,16 +367,19 @@ fn parse_cli() -> Result<ParsedOptions, ParseErrors> {
     }
 
     if let Some(source_path) = source_path {
-        Ok(ParsedOptions { source_path, color_size, dither_mode, depth })
+        Ok(ParsedOptions { source_path, dest_path, algorithm, color_space, distance_metric, color_size, dither_mode, depth, with_alpha, transparent_slot, refine_iterations, refine_threshold, sample_factor, linear_light, ordered_spread, blue_noise_size, noise_mask_path, serpentine, dither_strength, max_dither_error, edge_aware, threads })
     } else {
         Err(ParseErrors::MissingArgument("source path".to_string()))
     }
 }
 
 fn run_quantization_pipeline(opts: ParsedOptions) {
-    let ParsedOptions { source_path, color_size, dither_mode, depth } = opts;
+    let ParsedOptions { source_path, dest_path, algorithm, color_space, distance_metric, color_size, dither_mode, depth, with_alpha, transparent_slot, refine_iterations, refine_threshold, sample_factor, linear_light, ordered_spread, blue_noise_size, noise_mask_path, serpentine, dither_strength, max_dither_error, edge_aware, threads } = opts;
 
-    let dest_path = add_to_filename(&source_path, "_quant_dither");
+    let dest_path = match dest_path {
+        Some(dest_path) => dest_path.into_path_buf(),
+        None => add_to_filename(&source_path, "_quant_dither"),
+    };
     let absolute_source_path = path::absolute(&source_path).unwrap().into_os_string().into_string().unwrap();
     let absolute_dest_path = path::absolute(&dest_path).unwrap().into_os_string().into_string().unwrap();
 
@@ -290,32 +395,80 @@ filename: {}
 width, height: ({}, {})
 color type: {:?}, bits per pixel: {}, channel count: {}
     "#, file_name, image_width, image_height, image_color, image_color.bits_per_pixel(), image_color.channel_count());
-    let mut new_img = DynamicImage::new(image_width, image_height, image_color);
-    let mut recursive_octree = LeafOctree::new(depth);
 
-    let start = Instant::now();
-    for (_, _, rgba) in img.pixels() {
-        recursive_octree.add_color(rgba.to_rgb());
+    let quantizer = Quantizer::new()
+        .algorithm(algorithm)
+        .color_space(color_space)
+        .distance_metric(distance_metric)
+        .colors(color_size)
+        .depth(depth)
+        .dither(dither_mode)
+        .alpha(with_alpha)
+        .transparent_slot(transparent_slot)
+        .refine_iterations(refine_iterations)
+        .refine_threshold(refine_threshold)
+        .sample_factor(sample_factor)
+        .linear_light(linear_light)
+        .blue_noise_size(blue_noise_size)
+        .serpentine(serpentine)
+        .dither_strength(dither_strength)
+        .edge_aware(edge_aware)
+        .threads(threads);
+    let quantizer = match max_dither_error {
+        Some(max_dither_error) => quantizer.max_dither_error(max_dither_error),
+        None => quantizer,
+    };
+    let quantizer = match ordered_spread {
+        Some(ordered_spread) => quantizer.ordered_spread(ordered_spread),
+        None => quantizer,
+    };
+    let quantizer = match noise_mask_path {
+        Some(noise_mask_path) => match image::open(&noise_mask_path) {
+            Ok(mask) => quantizer.threshold_mask(mask.to_luma8()),
+            Err(err) => return println!("FileError: {}", err),
+        },
+        None => quantizer,
+    };
+    if let Err(err) = quantizer.validate() {
+        return println!("{}", err);
     }
+    let rgba_img = img.to_rgba8();
 
-    println!("\nseconds to initialize: {:?}", Instant::now() - start);
-    println!("tree leaves count before quantization: {} color/s", recursive_octree.get_leaf_nodes().len());
+    let start = Instant::now();
+    let mut generator = quantizer.build_generator(&rgba_img);
 
-    let palette = recursive_octree.make_palette(color_size);
-    println!("tree leaves count after quantization: {} color/s", recursive_octree.get_leaf_nodes().len());
+    println!("\nseconds to initialize: {:?}", Instant::now() - start);
+    println!("color count before quantization: {} color/s", generator.color_count());
 
-    print_palette(&palette);
+    let palette = quantizer.make_palette(&mut generator, &rgba_img);
+    println!("color count after quantization: {} color/s", generator.color_count());
 
     let start = Instant::now();
-    match dither_mode {
-        DitherMode::Base => base_quantize(&recursive_octree, &palette, &img, &mut new_img),
-        DitherMode::SierraLite | DitherMode::FloydSteinberg => quantize_dither_image(&recursive_octree, &palette, &img, &mut new_img, &dither_mode),
-    };
+    let quantized = quantizer.remap(generator.as_ref(), palette, &rgba_img);
     let duration = start.elapsed();
+    print_palette(&quantized.palette);
     println!("image quantization took: {:?}", duration);
-    println!("time per pixel: {:.6} ms", duration.as_secs_f64() / (new_img.width() * new_img.height()) as f64 * 1000.0);
-    println!("pixels: {}", new_img.width() * new_img.height());
+    println!("time per pixel: {:.6} ms", duration.as_secs_f64() / (image_width * image_height) as f64 * 1000.0);
+    println!("pixels: {}", image_width * image_height);
+
+    let extension = dest_path.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+    let indexed_result = match extension.as_str() {
+        "png" if fits_indexed(&quantized) => Some(save_indexed_png(&quantized, &absolute_dest_path)),
+        "png" => {
+            println!("too many colors with alpha for an indexed png, saving it in full color instead.");
+            None
+        },
+        "gif" => Some(save_indexed_gif(&quantized, &absolute_dest_path)),
+        _ => None,
+    };
+    if let Some(result) = indexed_result {
+        if let Err(err) = result {
+            println!("Image Save Error: {}", err);
+        }
+        return;
+    }
 
+    let new_img = DynamicImage::ImageRgba8(quantized.to_rgba_image());
     let dest_img = match image_color {
         ColorType::L8 => DynamicImage::ImageLuma8(new_img.to_luma8()),
         ColorType::L16 => DynamicImage::ImageLuma16(new_img.to_luma16()),
@@ -324,7 +477,7 @@ color type: {:?}, bits per pixel: {}, channel count: {}
         ColorType::Rgb8 => DynamicImage::ImageRgb8(new_img.to_rgb8()),
         ColorType::Rgb16 => DynamicImage::ImageRgb16(new_img.to_rgb16()),
         ColorType::Rgb32F => DynamicImage::ImageRgb32F(new_img.to_rgb32f()),
-        ColorType::Rgba8 => DynamicImage::ImageRgba8(new_img.to_rgba8()),
+        ColorType::Rgba8 => new_img,
         ColorType::Rgba16 => DynamicImage::ImageRgba16(new_img.to_rgba16()),
         ColorType::Rgba32F => DynamicImage::ImageRgba32F(new_img.to_rgba32f()),
         _ => return println!("Unsupported color type!"),
@@ -348,9 +501,31 @@ fn main() {
     Options:
         -h, --help     help
         -i, --input    file to quantize
+        -o, --output   destination file, .png and .gif are written as indexed images (default: <input>_quant_dither)
         -d, --depth    octree depth (2 to 8)
+        --algorithm    palette algorithm [octree, median-cut, wu, neuquant, morton]
+        --colorspace   color space palettes are built and matched in [srgb, linear, lab, oklab, yuv]
+        --distance     color distance for nearest colors and refinement [rgb, lab, cie76, cie94, ciede2000]
         -c, --color    number of colors in the octree.
-        --dither       modes for dithering [base, sierralite, floydsteinberg]
+        --dither       modes for dithering [base, sierralite, floydsteinberg, jjn, stucki, burkes, sierra3,
+                       tworowsierra, atkinson, shiaufan, shiaufan2, bayer, bayer<size>, bluenoise, riemersma, knoll,
+                       yliluoma] (bayer is bayer8)
+        --serpentine   alternate the scan direction every row when diffusing error
+        --dither-strength     share of the error that is diffused, 0.0 to 1.0 (default: 1.0)
+        --error-clamp         cap on the error a pixel takes on, per channel (default: none)
+        --edge-aware   stop diffusing error across strong edges, for screenshots and pixel art
+        --kernel       custom error diffusion kernel, rows split by ';', e.g. "- * 7; 3 5 1; /16"
+        --kernel-file  file holding a custom kernel, one row per line, '#' for comments
+        --spread       how far ordered dithering pushes colors, in channel units (default: the palette's spacing)
+        --noise-size   side of the generated blue noise mask, 2 to 256 (default: 64)
+        --noise-mask   grayscale image to threshold blue noise dithering against instead
+        --alpha        quantize alpha into the palette instead of keeping the source alpha
+        --transparent  reserve one palette color for fully transparent pixels
+        --linear-light average octree colors and diffuse dither error in linear light (srgb only)
+        --refine-iterations   k-means iterations to refine the palette with (default: 0)
+        --refine-threshold    stop refining once no color moves more than this, in --distance units (default: 8)
+        --sample-factor       neuquant learns from every nth pixel, 1 (best) to 30 (fastest) (default: 10)
+        --threads      build the octree from this many row bands in parallel, same output for any count (default: 1)
                     "#
                     );
             },
@@ -358,4 +533,3 @@ fn main() {
         }
     }
 }
-
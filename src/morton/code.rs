@@ -0,0 +1,38 @@
+use image::Rgb;
+
+// spreads the 8 bits of `c` out to every third bit of a 24 bit value.
+fn spread(c: u8) -> u32 {
+    let mut x = u32::from(c);
+    x = (x | x << 8) & 0x0000_f00f;
+    x = (x | x << 4) & 0x000c_30c3;
+    x = (x | x << 2) & 0x0024_9249;
+    x
+}
+
+// gathers every third bit of `x` back into 8 bits.
+fn compact(x: u32) -> u8 {
+    let mut x = x & 0x0024_9249;
+    x = (x | x >> 2) & 0x000c_30c3;
+    x = (x | x >> 4) & 0x0000_f00f;
+    x = (x | x >> 8) & 0xff;
+    x as u8
+}
+
+/// Interleaves the bits of a color, most significant first, red above green above blue.
+///
+/// Every 3 bits of the 24 bit code are the octree child index at one level, see
+/// `get_color_index`, so sorting codes groups every octree node into one contiguous run.
+pub fn encode(color: Rgb<u8>) -> u32 {
+    let [r, g, b] = color.0;
+    spread(r) << 2 | spread(g) << 1 | spread(b)
+}
+
+/// The color behind a code from `encode`.
+pub fn decode(code: u32) -> Rgb<u8> {
+    Rgb([compact(code >> 2), compact(code >> 1), compact(code)])
+}
+
+/// The octree child index of `code` at `level`, from 0 at the root to 7.
+pub fn child_index(code: u32, level: usize) -> usize {
+    (code >> (3 * (7 - level)) & 0b111) as usize
+}
@@ -1,80 +1,286 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::rc::Rc;
 
-use core::fmt;
-use crate::core::accum_octree::get_color_index;
-use std::{cell::RefCell, rc::Rc};
-use image::Rgb;
+use image::{Rgb, Rgba};
 
-#[derive(Clone, Debug)]
-pub struct MortonOctreeNode {
-    content: String,
-    children: [Option<Rc<RefCell<MortonOctreeNode>>>; 8],
+use crate::core::color_space::{from_linear_light, to_linear_light, ColorSpace};
+use crate::core::palette_generator::PaletteGenerator;
+use crate::core::rgb_helpers::{nearest_color_from_palette, ColorDistance, WeightedRgb};
+use crate::morton::code::{child_index, decode, encode};
+
+/// Pending codes are folded into the histogram once there are this many, or as many as
+/// it has entries, which keeps memory at about the number of distinct colors.
+const MIN_PENDING: usize = 1 << 16;
+
+/// A run of histogram entries, `start..end`. Splits only ever fall between octree siblings,
+/// so a node is always a run of neighbouring siblings, or everything below one of them.
+#[derive(Clone, Copy, Debug)]
+struct Node {
+    start: usize,
+    end: usize,
 }
-pub struct MortonOctree {
-    depth: usize,
-    root: MortonOctreeNode,
+
+/// Splitting `node` at `split` takes away `gain` of squared error. Ordered by gain,
+/// ties broken by Morton order so the palette doesn't depend on float noise.
+struct SplitCandidate {
+    gain: f64,
+    node: Node,
+    split: usize,
 }
 
-impl fmt::Display for MortonOctree {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let node = &self.root;
-        write!(f, "{}", node.to_string())
+impl PartialEq for SplitCandidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl Eq for SplitCandidate {}
+impl PartialOrd for SplitCandidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
     }
 }
+impl Ord for SplitCandidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.gain.total_cmp(&other.gain).then(other.node.start.cmp(&self.node.start))
+    }
+}
+
+/// Running sums over the histogram, so any node's pixel count, color sum and squared error
+/// take two lookups each.
+struct Moments {
+    count: Vec<u64>,
+    sum: Vec<[u64; 3]>,
+    square: Vec<u64>,
+}
+
+impl Moments {
+    fn new(histogram: &[(u32, u32)], linear_light: bool) -> Self {
+        let mut moments = Self { count: vec![0], sum: vec![[0; 3]], square: vec![0] };
+        for &(code, count) in histogram {
+            let count = u64::from(count);
+            let values = sample(code, linear_light);
+            let (last_sum, last_square) = (moments.sum[moments.sum.len() - 1], moments.square[moments.square.len() - 1]);
+            moments.count.push(moments.count[moments.count.len() - 1] + count);
+            moments.sum.push([0, 1, 2].map(|c| last_sum[c] + count * values[c]));
+            moments.square.push(last_square + count * values.iter().map(|v| v * v).sum::<u64>());
+        }
+        moments
+    }
 
-impl fmt::Display for MortonOctreeNode {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let children: Vec<String> = self.children.iter()
-            .filter_map(|c| c.as_ref().map(|x| format!("{}", x.borrow())))
-            .collect();
-        write!(f, "<str: {}, children: [{}]>", self.content, children.join(", "))
+    fn count(&self, node: Node) -> u64 {
+        self.count[node.end] - self.count[node.start]
     }
+
+    fn sum(&self, node: Node) -> [u64; 3] {
+        [0, 1, 2].map(|c| self.sum[node.end][c] - self.sum[node.start][c])
+    }
+
+    /// Squared error of every pixel in `node` against the node's mean.
+    fn error(&self, node: Node) -> f64 {
+        let count = self.count(node) as f64;
+        let square = (self.square[node.end] - self.square[node.start]) as f64;
+        square - self.sum(node).iter().map(|&s| (s as f64).powi(2) / count).sum::<f64>()
+    }
+}
+
+// what a code adds to the sums: its sRGB bytes, or 12-bit linear light.
+fn sample(code: u32, linear_light: bool) -> [u64; 3] {
+    let Rgb(rgb) = decode(code);
+    rgb.map(|c| if linear_light { u64::from(to_linear_light(c)) } else { u64::from(c) })
+}
+
+/// An octree kept as a sorted array of Morton codes instead of linked nodes, see `code::encode`.
+///
+/// Colors are only recorded as codes while building. `make_palette` sorts them into a
+/// histogram, where every octree node is a contiguous run, then starts from one entry and
+/// keeps cutting the entry that gains the most in two, between octree siblings, where the
+/// summed squared error drops the most. Every cut adds one color, so the palette is exactly
+/// `min(color_count, colors)` long, counting colors at the tree's depth.
+/// Nodes are never allocated: a node is just a range of the histogram.
+///
+/// Colors fall into the palette entry whose node they share, otherwise `force_find_color`
+/// looks for the nearest entry. Alpha is never part of the tree, every palette entry is opaque.
+pub struct MortonOctree {
+    depth: usize,
+    linear_light: bool,
+    distance: Rc<dyn ColorDistance>,
+    /// Codes added since the last flush, in no particular order.
+    pending: Vec<u32>,
+    /// Distinct codes in ascending order, with how often each was added.
+    histogram: Vec<(u32, u32)>,
+    /// The code range every palette entry covers, ascending.
+    leaves: Vec<(u32, u32)>,
+    palette: Vec<Rgba<u8>>,
 }
 
 impl MortonOctree {
+    /// `depth` caps how many levels the tree splits into, from 1 to 8.
     pub fn new(depth: usize) -> Self {
         Self {
-            depth,
-            root: MortonOctreeNode::new("Comfy".to_string()),
+            depth: depth.clamp(1, 8),
+            linear_light: false,
+            distance: Rc::new(WeightedRgb::new(ColorSpace::Srgb)),
+            pending: Vec::new(),
+            histogram: Vec::new(),
+            leaves: Vec::new(),
+            palette: Vec::new(),
         }
     }
-    pub fn add_color(&mut self, color: Rgb<u8>) {
-        self.root.add_node(color, 0, self.depth);
+    /// Averages in linear light instead of on the sRGB bytes, like `LeafOctree::linear_light`.
+    pub fn linear_light(mut self, linear_light: bool) -> Self {
+        self.linear_light = linear_light;
+        self
+    }
+    /// How the nearest entry is found for colors outside every palette node.
+    pub fn distance(mut self, distance: Rc<dyn ColorDistance>) -> Self {
+        self.distance = distance;
+        self
     }
-    pub fn traverse(&self) -> String {
-        let mut s = String::new();
-        self.root.traverse(&mut s);
 
-        s
+    /// Sorts the pending codes and merges them into the histogram.
+    fn flush(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+        self.pending.sort_unstable();
+        let mut merged = Vec::with_capacity(self.histogram.len() + self.pending.len());
+        let mut histogram = self.histogram.iter().copied().peekable();
+        for code in self.pending.drain(..) {
+            while let Some(&(smaller, count)) = histogram.peek() && smaller < code {
+                merged.push((smaller, count));
+                histogram.next();
+            }
+            match merged.last_mut() {
+                Some((last, count)) if *last == code => *count += 1,
+                _ => match histogram.next_if(|&(equal, _)| equal == code) {
+                    Some((_, count)) => merged.push((code, count + 1)),
+                    None => merged.push((code, 1)),
+                },
+            }
+        }
+        merged.extend(histogram);
+        self.histogram = merged;
+    }
+
+    /// How many top digits every code in `node` shares, up to `depth`.
+    fn shared_levels(&self, node: Node) -> usize {
+        let (first, last) = (self.histogram[node.start].0, self.histogram[node.end - 1].0);
+        (0..self.depth).take_while(|&level| child_index(first, level) == child_index(last, level)).count()
+    }
+
+    /// The best place to cut `node` in two, between the children of the deepest octree node
+    /// holding all of it. `None` once it holds a single color at the tree's depth.
+    fn best_split(&self, node: Node, moments: &Moments) -> Option<SplitCandidate> {
+        let level = self.shared_levels(node);
+        if level >= self.depth {
+            return None;
+        }
+        let run = &self.histogram[node.start..node.end];
+        let mut best: Option<(f64, usize)> = None;
+        let mut start = node.start;
+        while start < node.end {
+            let digit = child_index(self.histogram[start].0, level);
+            let split = node.start + run.partition_point(|&(code, _)| child_index(code, level) <= digit);
+            if split < node.end {
+                let error = moments.error(Node { start: node.start, end: split }) + moments.error(Node { start: split, end: node.end });
+                if best.is_none_or(|(least, _)| error < least) {
+                    best = Some((error, split));
+                }
+            }
+            start = split;
+        }
+        best.map(|(error, split)| SplitCandidate { gain: moments.error(node) - error, node, split })
+    }
+
+    /// The codes a palette entry covers: the octree blocks its first and last colors sit in,
+    /// one level below the node holding both, and everything in between.
+    fn code_range(&self, node: Node) -> (u32, u32) {
+        let level = (self.shared_levels(node) + 1).min(self.depth);
+        let shift = 3 * (8 - level);
+        let (first, last) = (self.histogram[node.start].0, self.histogram[node.end - 1].0);
+        (first >> shift << shift, ((last >> shift) + 1) << shift)
     }
 }
 
-impl MortonOctreeNode {
-    pub fn new(content: String) -> Self {
-        Self {
-            content,
-            children: std::array::from_fn(|_| None),
+impl PaletteGenerator for MortonOctree {
+    fn add_color(&mut self, color: Rgba<u8>) {
+        let [r, g, b, _] = color.0;
+        self.pending.push(encode(Rgb([r, g, b])));
+        if self.pending.len() >= self.histogram.len().max(MIN_PENDING) {
+            self.flush();
         }
     }
-    pub fn traverse(&self, out: &mut String) {
-        for child in self.children.iter() {
-            if let Some(child) = child {
-                let child = child.as_ref().borrow();
-                out.push_str(&child.content);
-                child.traverse(out);
+
+    fn make_palette(&mut self, color_count: i32) -> Vec<Rgba<u8>> {
+        self.flush();
+        self.leaves.clear();
+        self.palette.clear();
+        if self.histogram.is_empty() {
+            return Vec::new();
+        }
+        let color_count = color_count.max(1) as usize;
+        let moments = Moments::new(&self.histogram, self.linear_light);
+
+        let mut leaves = Vec::new();
+        let mut queue = BinaryHeap::new();
+        let root = Node { start: 0, end: self.histogram.len() };
+        match self.best_split(root, &moments) {
+            Some(candidate) => queue.push(candidate),
+            None => leaves.push(root),
+        }
+        while let Some(SplitCandidate { node, split, .. }) = queue.pop() {
+            if leaves.len() + queue.len() + 1 >= color_count {
+                leaves.push(node);
+                continue;
+            }
+            for half in [Node { start: node.start, end: split }, Node { start: split, end: node.end }] {
+                match self.best_split(half, &moments) {
+                    Some(candidate) => queue.push(candidate),
+                    None => leaves.push(half),
+                }
             }
         }
+
+        leaves.sort_unstable_by_key(|node| node.start);
+        for node in leaves {
+            let count = moments.count(node);
+            let [r, g, b] = moments.sum(node).map(|s| {
+                let mean = (s + count / 2) / count;
+                if self.linear_light { from_linear_light(mean as u16) } else { mean as u8 }
+            });
+            self.palette.push(Rgba([r, g, b, u8::MAX]));
+            self.leaves.push(self.code_range(node));
+        }
+
+        self.palette.clone()
     }
-    pub fn add_node(&mut self, color: Rgb<u8>, level: usize, depth: usize) {
-        if level >= depth {
-            return;
+
+    fn get_palette_index(&self, color: Rgba<u8>, force_find_color: bool) -> Option<usize> {
+        let [r, g, b, _] = color.0;
+        let code = encode(Rgb([r, g, b]));
+        let after = self.leaves.partition_point(|&(start, _)| start <= code);
+        if after > 0 && code < self.leaves[after - 1].1 {
+            return Some(after - 1);
+        }
+        if force_find_color && !self.palette.is_empty() {
+            return Some(nearest_color_from_palette(&self.palette, &self.normalize(color), self.distance.as_ref()));
         }
-        let index = get_color_index(color, level);
-        let child = &mut self.children[index];
-        if child.is_none() {
-            let node = Rc::new(RefCell::new(MortonOctreeNode::new("test".to_string())));
-            self.children[index] = Some(node);
+        None
+    }
+
+    fn color_count(&self) -> usize {
+        if !self.palette.is_empty() {
+            return self.palette.len();
         }
-        let mut node = self.children[index].as_ref().unwrap().borrow_mut();
-        node.add_node(color, level + 1, depth);
+        let mut pending = self.pending.clone();
+        pending.sort_unstable();
+        pending.dedup();
+        let new_codes = pending.iter().filter(|&&code| self.histogram.binary_search_by_key(&code, |&(code, _)| code).is_err()).count();
+        self.histogram.len() + new_codes
+    }
+
+    fn has_alpha(&self) -> bool {
+        false
     }
 }
@@ -0,0 +1,522 @@
+use image::{DynamicImage, GrayImage, Pixel, Rgba, RgbaImage};
+use thiserror::Error;
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::thread;
+
+use crate::core::accum_octree::LeafOctree;
+use crate::core::fixed_palette::FixedPalette;
+use crate::core::kmeans::refine_palette;
+use crate::core::median_cut::MedianCut;
+use crate::core::neuquant::NeuQuant;
+use crate::core::palette_generator::PaletteGenerator;
+use crate::core::rgb_helpers::{Cie76, Cie94, Ciede2000, ColorDistance, EuclideanLab, WeightedRgb};
+use crate::core::wu::WuQuantizer;
+use crate::dither::{base_quantize, quantize_dither_image, DiffusionControls, DitherMode, ErrorDiffusion};
+use crate::dither::ordered::{is_bayer_size, ordered_quantize, palette_spread, ThresholdMap};
+use crate::dither::pattern::{pattern_quantize, MixingMethod, PatternDither};
+use crate::dither::riemersma::riemersma_quantize;
+use crate::morton::octree::MortonOctree;
+
+pub use crate::core::color_space::ColorSpace;
+
+/// The palette algorithm.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Algorithm {
+    /// `LeafOctree`.
+    #[default]
+    Octree,
+    /// `MedianCut`, tends to do better on flat-color artwork.
+    MedianCut,
+    /// `WuQuantizer`, Xiaolin Wu's variance minimization. Ignores `alpha`,
+    /// the source alpha is carried through instead.
+    Wu,
+    /// `NeuQuant`, a Kohonen neural net trained on every `sample_factor`th pixel.
+    NeuQuant,
+    /// `MortonOctree`, an octree flattened into sorted Morton codes. Uses `depth` like
+    /// `Octree`, up to 8, and ignores `alpha` like `Wu`.
+    Morton,
+}
+
+/// How close two colors are, wherever a nearest palette entry has to be searched for.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DistanceMetric {
+    /// `WeightedRgb`, weighted squared distance on the color space's own channels.
+    #[default]
+    WeightedRgb,
+    /// `EuclideanLab`, squared distance in CIELAB.
+    EuclideanLab,
+    /// `Cie76`, ΔE*ab.
+    Cie76,
+    /// `Cie94`, graphic arts weights.
+    Cie94,
+    /// `Ciede2000`, the most accurate and by far the slowest.
+    Ciede2000,
+}
+
+#[derive(Error, Debug)]
+pub enum QuantizeError {
+    #[error("Color count {0} is below 2.")]
+    InvalidColorCount(i32),
+    #[error("Depth {0} must be more than 2 and less than or equal to 10.")]
+    InvalidDepth(usize),
+    #[error("Sample factor {0} must be between 1 and 30.")]
+    InvalidSampleFactor(usize),
+    #[error("Linear light only works with the sRGB color space, not {0:?}.")]
+    LinearLightNeedsSrgb(ColorSpace),
+    #[error("Bayer matrix size {0} must be a power of two, or three times one, from 2 to 256.")]
+    InvalidBayerSize(usize),
+    #[error("Ordered dither spread {0} must be a finite number of at least 0.")]
+    InvalidSpread(f32),
+    #[error("Blue noise size {0} must be between 2 and 256.")]
+    InvalidBlueNoiseSize(usize),
+    #[error("The threshold mask is empty.")]
+    EmptyThresholdMask,
+    #[error("Dither strength {0} must be between 0 and 1.")]
+    InvalidDitherStrength(f32),
+    #[error("Thread count must be at least 1.")]
+    InvalidThreadCount,
+}
+
+/// A palette plus one palette index per pixel.
+#[derive(Clone, Debug)]
+pub struct QuantizedImage {
+    pub palette: Vec<Rgba<u8>>,
+    /// Row-major palette indices, `width * height` long.
+    pub indices: Vec<u32>,
+    pub width: u32,
+    pub height: u32,
+    /// Source alpha, carried through untouched. `None` if the source was fully opaque,
+    /// or if alpha was quantized into the palette instead.
+    pub alpha: Option<Vec<u8>>,
+}
+
+impl QuantizedImage {
+    /// Expands the palette indices back into a full color image.
+    pub fn to_rgba_image(&self) -> RgbaImage {
+        RgbaImage::from_fn(self.width, self.height, |x, y| {
+            let pixel_index = (y * self.width + x) as usize;
+            let [r, g, b, a] = self.palette[self.indices[pixel_index] as usize].0;
+            let a = self.alpha.as_ref().map_or(a, |alpha| alpha[pixel_index]);
+            Rgba([r, g, b, a])
+        })
+    }
+}
+
+/// Builder for the quantization pipeline.
+///
+/// ```no_run
+/// use imgquant::{DitherMode, Quantizer};
+///
+/// let img = image::open("images/koishi.jpg").unwrap();
+/// let quantized = Quantizer::new()
+///     .colors(16)
+///     .dither(DitherMode::SierraLite)
+///     .quantize_image(&img)
+///     .unwrap();
+/// quantized.to_rgba_image().save("koishi_16.png").unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct Quantizer {
+    algorithm: Algorithm,
+    colors: i32,
+    depth: usize,
+    dither_mode: DitherMode,
+    color_space: ColorSpace,
+    distance_metric: DistanceMetric,
+    with_alpha: bool,
+    transparent_slot: bool,
+    refine_iterations: usize,
+    refine_threshold: u32,
+    sample_factor: usize,
+    linear_light: bool,
+    ordered_spread: Option<f32>,
+    blue_noise_size: usize,
+    threshold_mask: Option<GrayImage>,
+    serpentine: bool,
+    dither_strength: f32,
+    max_dither_error: Option<u16>,
+    edge_aware: bool,
+    threads: usize,
+}
+
+impl Default for Quantizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Quantizer {
+    pub fn new() -> Self {
+        Self {
+            algorithm: Algorithm::Octree,
+            colors: 256,
+            depth: 6,
+            dither_mode: DitherMode::FloydSteinberg,
+            color_space: ColorSpace::Srgb,
+            distance_metric: DistanceMetric::WeightedRgb,
+            with_alpha: false,
+            transparent_slot: false,
+            refine_iterations: 0,
+            refine_threshold: 8,
+            sample_factor: 10,
+            linear_light: false,
+            ordered_spread: None,
+            blue_noise_size: 64,
+            threshold_mask: None,
+            serpentine: false,
+            dither_strength: 1.0,
+            max_dither_error: None,
+            edge_aware: false,
+            threads: 1,
+        }
+    }
+    /// Defaults to `Algorithm::Octree`.
+    pub fn algorithm(mut self, algorithm: Algorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+    /// Maximum number of colors in the palette. Defaults to 256.
+    pub fn colors(mut self, colors: i32) -> Self {
+        self.colors = colors;
+        self
+    }
+    /// Octree depth, from 3 to 10. Defaults to 6.
+    pub fn depth(mut self, depth: usize) -> Self {
+        self.depth = depth;
+        self
+    }
+    /// Defaults to `DitherMode::FloydSteinberg`.
+    pub fn dither(mut self, dither_mode: DitherMode) -> Self {
+        self.dither_mode = dither_mode;
+        self
+    }
+    /// The space palettes are built and colors matched in. Defaults to `ColorSpace::Srgb`.
+    pub fn color_space(mut self, color_space: ColorSpace) -> Self {
+        self.color_space = color_space;
+        self
+    }
+    /// Used for nearest palette entries and k-means. Defaults to `DistanceMetric::WeightedRgb`.
+    pub fn distance_metric(mut self, distance_metric: DistanceMetric) -> Self {
+        self.distance_metric = distance_metric;
+        self
+    }
+    /// Quantize alpha along with the color, giving an RGBA palette. Defaults to `false`,
+    /// where the palette is opaque and the source alpha is carried through per pixel.
+    pub fn alpha(mut self, with_alpha: bool) -> Self {
+        self.with_alpha = with_alpha;
+        self
+    }
+    /// Reserve the last palette index for fully transparent pixels. Defaults to `false`.
+    ///
+    /// Pixels with alpha 0 are then left out of the tree, always map to that index,
+    /// and never diffuse error into their neighbours. The slot counts towards `colors`.
+    pub fn transparent_slot(mut self, transparent_slot: bool) -> Self {
+        self.transparent_slot = transparent_slot;
+        self
+    }
+    /// Runs up to this many k-means iterations over the image colors after the palette
+    /// is built, seeded with that palette. Defaults to 0, no refinement.
+    pub fn refine_iterations(mut self, refine_iterations: usize) -> Self {
+        self.refine_iterations = refine_iterations;
+        self
+    }
+    /// Refinement stops early once no palette entry moves more than this, in the units of the
+    /// selected `distance_metric`, so the CIE metrics, scaled by 100, need a larger one. Defaults to 8.
+    pub fn refine_threshold(mut self, refine_threshold: u32) -> Self {
+        self.refine_threshold = refine_threshold;
+        self
+    }
+    /// NeuQuant learns from every `sample_factor`th pixel, from 1 (best) to 30 (fastest). Defaults to 10.
+    pub fn sample_factor(mut self, sample_factor: usize) -> Self {
+        self.sample_factor = sample_factor;
+        self
+    }
+    /// Average octree leaves and diffuse dither error in linear light, so dithered
+    /// midtones keep their brightness. Needs `ColorSpace::Srgb`. Defaults to `false`.
+    pub fn linear_light(mut self, linear_light: bool) -> Self {
+        self.linear_light = linear_light;
+        self
+    }
+
+    /// How far ordered dithering pushes colors, in channel units: every threshold
+    /// lies within half of this either way. Defaults to the palette's own spacing, see `palette_spread`.
+    pub fn ordered_spread(mut self, ordered_spread: f32) -> Self {
+        self.ordered_spread = Some(ordered_spread);
+        self
+    }
+
+    /// Side of the blue noise mask generated for `DitherMode::BlueNoise`, from 2 to 256. Defaults to 64.
+    pub fn blue_noise_size(mut self, blue_noise_size: usize) -> Self {
+        self.blue_noise_size = blue_noise_size;
+        self
+    }
+    /// Tiles this grayscale mask for `DitherMode::BlueNoise` instead of generating one,
+    /// thresholding every pixel by the brightness under it.
+    pub fn threshold_mask(mut self, threshold_mask: GrayImage) -> Self {
+        self.threshold_mask = Some(threshold_mask);
+        self
+    }
+
+    /// Scan every other row right to left when diffusing error, mirroring the kernel,
+    /// to break up diagonal worm artifacts. Defaults to `false`.
+    pub fn serpentine(mut self, serpentine: bool) -> Self {
+        self.serpentine = serpentine;
+        self
+    }
+
+    /// Share of the quantization error error diffusion passes on, from 0 to 1. Defaults to 1.
+    pub fn dither_strength(mut self, dither_strength: f32) -> Self {
+        self.dither_strength = dither_strength;
+        self
+    }
+    /// Caps the error a pixel can take on when diffusing, per channel in channel units. Defaults to no cap.
+    pub fn max_dither_error(mut self, max_dither_error: u16) -> Self {
+        self.max_dither_error = Some(max_dither_error);
+        self
+    }
+    /// Fade diffused error out across strong gradients, for UI screenshots and pixel art. Defaults to `false`.
+    pub fn edge_aware(mut self, edge_aware: bool) -> Self {
+        self.edge_aware = edge_aware;
+        self
+    }
+    /// Builds the octree from this many row bands at once, one thread each. The palette and
+    /// output don't depend on it. Other algorithms build on one thread. Defaults to 1.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    pub fn validate(&self) -> Result<(), QuantizeError> {
+        if self.colors < 2 {
+            return Err(QuantizeError::InvalidColorCount(self.colors));
+        }
+        if self.depth <= 2 || self.depth > 10 {
+            return Err(QuantizeError::InvalidDepth(self.depth));
+        }
+        if !(1..=30).contains(&self.sample_factor) {
+            return Err(QuantizeError::InvalidSampleFactor(self.sample_factor));
+        }
+        if self.linear_light && self.color_space != ColorSpace::Srgb {
+            return Err(QuantizeError::LinearLightNeedsSrgb(self.color_space));
+        }
+        if let DitherMode::Bayer(size) = self.dither_mode && !is_bayer_size(size) {
+            return Err(QuantizeError::InvalidBayerSize(size));
+        }
+        if self.dither_mode == DitherMode::BlueNoise && self.threshold_mask.is_none() && !(2..=256).contains(&self.blue_noise_size) {
+            return Err(QuantizeError::InvalidBlueNoiseSize(self.blue_noise_size));
+        }
+        if let Some(mask) = &self.threshold_mask && (mask.width() == 0 || mask.height() == 0) {
+            return Err(QuantizeError::EmptyThresholdMask);
+        }
+        if !(0.0..=1.0).contains(&self.dither_strength) {
+            return Err(QuantizeError::InvalidDitherStrength(self.dither_strength));
+        }
+        if let Some(spread) = self.ordered_spread && !(spread.is_finite() && spread >= 0.0) {
+            return Err(QuantizeError::InvalidSpread(spread));
+        }
+        if self.threads == 0 {
+            return Err(QuantizeError::InvalidThreadCount);
+        }
+        Ok(())
+    }
+
+    pub fn quantize_image(&self, image: &DynamicImage) -> Result<QuantizedImage, QuantizeError> {
+        self.quantize(&image.to_rgba8())
+    }
+
+    pub fn quantize(&self, image: &RgbaImage) -> Result<QuantizedImage, QuantizeError> {
+        self.validate()?;
+        let mut generator = self.build_generator(image);
+        let palette = self.make_palette(&mut generator, image);
+
+        Ok(self.remap(generator.as_ref(), palette, image))
+    }
+
+    /// First pipeline stage: feeds every pixel of `image` into a fresh palette generator.
+    pub fn build_generator(&self, image: &RgbaImage) -> Box<dyn PaletteGenerator> {
+        if self.algorithm == Algorithm::Octree && self.threads > 1 {
+            return Box::new(self.build_octree(image));
+        }
+        let mut generator: Box<dyn PaletteGenerator> = match (self.algorithm, self.with_alpha) {
+            (Algorithm::Octree, false) => Box::new(LeafOctree::new(self.depth).linear_light(self.linear_light)),
+            (Algorithm::Octree, true) => Box::new(LeafOctree::with_alpha(self.depth).linear_light(self.linear_light)),
+            (Algorithm::MedianCut, false) => Box::new(MedianCut::new().color_space(self.color_space).distance(self.color_distance())),
+            (Algorithm::MedianCut, true) => Box::new(MedianCut::with_alpha().color_space(self.color_space).distance(self.color_distance())),
+            (Algorithm::Wu, _) => Box::new(WuQuantizer::new()),
+            (Algorithm::Morton, _) => Box::new(MortonOctree::new(self.depth).linear_light(self.linear_light).distance(self.color_distance())),
+            (Algorithm::NeuQuant, false) => Box::new(NeuQuant::new(self.sample_factor).color_space(self.color_space).distance(self.color_distance())),
+            (Algorithm::NeuQuant, true) => Box::new(NeuQuant::with_alpha(self.sample_factor).color_space(self.color_space).distance(self.color_distance())),
+        };
+        for rgba in self.palette_colors(image) {
+            generator.add_color(rgba);
+        }
+
+        generator
+    }
+
+    /// Splits `image` into one band of rows per thread, builds an octree from each band,
+    /// then merges them in band order.
+    fn build_octree(&self, image: &RgbaImage) -> LeafOctree {
+        let new_octree = || {
+            let octree = if self.with_alpha { LeafOctree::with_alpha(self.depth) } else { LeafOctree::new(self.depth) };
+            octree.linear_light(self.linear_light)
+        };
+        let band_rows = image.height().div_ceil(self.threads as u32) as usize;
+        let band_len = (band_rows * image.width() as usize * 4).max(1);
+        let bands: Vec<LeafOctree> = thread::scope(|scope| {
+            let handles: Vec<_> = image.as_raw()
+                .chunks(band_len)
+                .map(|band| scope.spawn(move || {
+                    let mut octree = new_octree();
+                    band.chunks_exact(4)
+                        .filter_map(|channels| self.palette_color(*Rgba::from_slice(channels)))
+                        .for_each(|rgba| octree.add_color(rgba));
+                    octree
+                }))
+                .collect();
+            handles.into_iter().map(|handle| handle.join().expect("octree band thread panicked")).collect()
+        });
+
+        let mut octree = new_octree();
+        for band in &bands {
+            octree.merge(band);
+        }
+        octree
+    }
+
+    /// Second pipeline stage: reduces the generator to a palette, with the transparent slot last if requested.
+    /// The palette is still in the quantizer's color space, `remap` converts it back to sRGB.
+    ///
+    /// When refining, the generator is swapped for a `FixedPalette` of the refined colors,
+    /// since its own buckets no longer line up with the palette entries.
+    pub fn make_palette(&self, generator: &mut Box<dyn PaletteGenerator>, image: &RgbaImage) -> Vec<Rgba<u8>> {
+        let color_count = if self.transparent_slot { self.colors - 1 } else { self.colors };
+        let mut palette = generator.make_palette(color_count);
+
+        if self.refine_iterations > 0 {
+            let mut histogram = HashMap::<Rgba<u8>, u32>::new();
+            for rgba in self.palette_colors(image) {
+                *histogram.entry(generator.normalize(rgba)).or_insert(0) += 1;
+            }
+            let mut histogram: Vec<(Rgba<u8>, u32)> = histogram.into_iter().collect();
+            histogram.sort_unstable_by_key(|(color, _)| color.0);
+
+            let distance = self.color_distance();
+            refine_palette(&mut palette, &histogram, self.refine_iterations, self.refine_threshold, distance.as_ref());
+
+            let mut fixed_palette = FixedPalette::new(palette.clone(), generator.has_alpha()).distance(distance);
+            for &(color, _) in &histogram {
+                fixed_palette.add_color(color);
+            }
+            *generator = Box::new(fixed_palette);
+        }
+
+        if self.transparent_slot {
+            palette.push(self.color_space.encode(Rgba([0, 0, 0, 0])));
+        }
+
+        palette
+    }
+
+    /// The selected distance metric, measuring colors in the quantizer's color space.
+    pub fn color_distance(&self) -> Rc<dyn ColorDistance> {
+        match self.distance_metric {
+            DistanceMetric::WeightedRgb => Rc::new(WeightedRgb::new(self.color_space)),
+            DistanceMetric::EuclideanLab => Rc::new(EuclideanLab::new(self.color_space)),
+            DistanceMetric::Cie76 => Rc::new(Cie76::new(self.color_space)),
+            DistanceMetric::Cie94 => Rc::new(Cie94::new(self.color_space)),
+            DistanceMetric::Ciede2000 => Rc::new(Ciede2000::new(self.color_space)),
+        }
+    }
+
+    /// The colors palettes are built from, in the quantizer's color space.
+    /// Fully transparent pixels are left out when they get their own slot.
+    fn palette_colors<'a>(&'a self, image: &'a RgbaImage) -> impl Iterator<Item = Rgba<u8>> + 'a {
+        image.pixels().filter_map(|rgba| self.palette_color(*rgba))
+    }
+
+    fn palette_color(&self, rgba: Rgba<u8>) -> Option<Rgba<u8>> {
+        (!(self.transparent_slot && rgba.0[3] == 0)).then(|| self.color_space.encode(rgba))
+    }
+
+    /// `image` converted to the quantizer's color space, borrowed as is for sRGB.
+    fn working_image<'a>(&self, image: &'a RgbaImage) -> Cow<'a, RgbaImage> {
+        match self.color_space {
+            ColorSpace::Srgb => Cow::Borrowed(image),
+            color_space => {
+                let mut working = image.clone();
+                working.pixels_mut().for_each(|rgba| *rgba = color_space.encode(*rgba));
+                Cow::Owned(working)
+            },
+        }
+    }
+
+    fn diffusion_controls(&self) -> DiffusionControls {
+        let controls = DiffusionControls::new()
+            .strength(self.dither_strength)
+            .edge_aware(self.edge_aware)
+            .linear_light(self.linear_light);
+        match self.max_dither_error {
+            Some(max_dither_error) => controls.max_error(max_dither_error),
+            None => controls,
+        }
+    }
+
+    /// The threshold map of an ordered dither mode, spread over `palette` unless the spread was set.
+    fn threshold_map(&self, palette: &[Rgba<u8>]) -> ThresholdMap {
+        let colors = if self.transparent_slot { &palette[..palette.len() - 1] } else { palette };
+        let spread = self.ordered_spread.unwrap_or_else(|| palette_spread(colors));
+        match (&self.dither_mode, &self.threshold_mask) {
+            (&DitherMode::Bayer(size), _) => ThresholdMap::bayer(size, spread, self.linear_light).expect("bayer size is validated"),
+            (DitherMode::BlueNoise, Some(mask)) => ThresholdMap::from_mask(mask, spread, self.linear_light),
+            (DitherMode::BlueNoise, None) => ThresholdMap::blue_noise(self.blue_noise_size, spread, self.linear_light),
+            (dither_mode, _) => panic!("{:?} has no threshold map!", dither_mode),
+        }
+    }
+
+    /// Last pipeline stage: maps every pixel of `image` onto `palette`, dithering if requested.
+    ///
+    /// `palette` must come from `make_palette` on the same quantizer. The returned palette is sRGB.
+    pub fn remap(&self, generator: &dyn PaletteGenerator, palette: Vec<Rgba<u8>>, image: &RgbaImage) -> QuantizedImage {
+        let transparent_index = self.transparent_slot.then(|| palette.len() as u32 - 1);
+        let working = self.working_image(image);
+        let indices = match &self.dither_mode {
+            DitherMode::Base => base_quantize(generator, &working, transparent_index),
+            DitherMode::Bayer(_) | DitherMode::BlueNoise => {
+                let thresholds = self.threshold_map(&palette);
+                ordered_quantize(generator, &palette, &working, &thresholds, transparent_index, self.color_distance().as_ref(), self.linear_light)
+            },
+            DitherMode::Knoll | DitherMode::Yliluoma => {
+                let method = if self.dither_mode == DitherMode::Knoll { MixingMethod::Knoll } else { MixingMethod::Yliluoma };
+                let colors = if self.transparent_slot { &palette[..palette.len() - 1] } else { &palette[..] };
+                let dither = PatternDither::new(method).color_space(self.color_space).linear_light(self.linear_light);
+                pattern_quantize(generator, colors, &working, &dither, transparent_index, self.color_distance().as_ref())
+            },
+            DitherMode::Riemersma => riemersma_quantize(generator, &palette, &working, transparent_index, self.color_distance().as_ref(), &self.diffusion_controls()),
+            dither_mode => {
+                let kernel = dither_mode.diffusion_kernel().expect("every other mode diffuses error");
+                let diffusion = ErrorDiffusion::new(kernel)
+                    .serpentine(self.serpentine)
+                    .controls(self.diffusion_controls());
+                quantize_dither_image(generator, &palette, &working, &diffusion, transparent_index, self.color_distance().as_ref())
+            },
+        };
+        let palette = palette.into_iter().map(|rgba| self.color_space.decode(rgba)).collect();
+        let alpha = if !generator.has_alpha() && image.pixels().any(|rgba| rgba.0[3] != u8::MAX) {
+            Some(image.pixels().map(|rgba| rgba.0[3]).collect())
+        } else {
+            None
+        };
+
+        QuantizedImage {
+            palette,
+            indices,
+            width: image.width(),
+            height: image.height(),
+            alpha,
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use image::Rgba;
+use imgquant::core::rgb_helpers::{cie76, cie94, ciede2000, Ciede2000, ColorDistance, EuclideanLab, WeightedRgb};
+use imgquant::ColorSpace;
+
+/// Sharma, Wu and Dalal (2005), "The CIEDE2000 color-difference formula", table 1:
+/// two CIELAB colors and their ΔE00, rounded to four decimals.
+const CIEDE2000_PAIRS: [([f32; 3], [f32; 3], f32); 34] = [
+    ([50.0, 2.6772, -79.7751], [50.0, 0.0, -82.7485], 2.0425),
+    ([50.0, 3.1571, -77.2803], [50.0, 0.0, -82.7485], 2.8615),
+    ([50.0, 2.8361, -74.0200], [50.0, 0.0, -82.7485], 3.4412),
+    ([50.0, -1.3802, -84.2814], [50.0, 0.0, -82.7485], 1.0000),
+    ([50.0, -1.1848, -84.8006], [50.0, 0.0, -82.7485], 1.0000),
+    ([50.0, -0.9009, -85.5211], [50.0, 0.0, -82.7485], 1.0000),
+    ([50.0, 0.0, 0.0], [50.0, -1.0, 2.0], 2.3669),
+    ([50.0, -1.0, 2.0], [50.0, 0.0, 0.0], 2.3669),
+    ([50.0, 2.4900, -0.0010], [50.0, -2.4900, 0.0009], 7.1792),
+    ([50.0, 2.4900, -0.0010], [50.0, -2.4900, 0.0010], 7.1792),
+    ([50.0, 2.4900, -0.0010], [50.0, -2.4900, 0.0011], 7.2195),
+    ([50.0, 2.4900, -0.0010], [50.0, -2.4900, 0.0012], 7.2195),
+    ([50.0, -0.0010, 2.4900], [50.0, 0.0009, -2.4900], 4.8045),
+    ([50.0, -0.0010, 2.4900], [50.0, 0.0010, -2.4900], 4.8045),
+    ([50.0, -0.0010, 2.4900], [50.0, 0.0011, -2.4900], 4.7461),
+    ([50.0, 2.5000, 0.0], [50.0, 0.0, -2.5000], 4.3065),
+    ([50.0, 2.5000, 0.0], [73.0, 25.0, -18.0], 27.1492),
+    ([50.0, 2.5000, 0.0], [61.0, -5.0, 29.0], 22.8977),
+    ([50.0, 2.5000, 0.0], [56.0, -27.0, -3.0], 31.9030),
+    ([50.0, 2.5000, 0.0], [58.0, 24.0, 15.0], 19.4535),
+    ([50.0, 2.5000, 0.0], [50.0, 3.1736, 0.5854], 1.0000),
+    ([50.0, 2.5000, 0.0], [50.0, 3.2972, 0.0], 1.0000),
+    ([50.0, 2.5000, 0.0], [50.0, 1.8634, 0.5757], 1.0000),
+    ([50.0, 2.5000, 0.0], [50.0, 3.2592, 0.3350], 1.0000),
+    ([60.2574, -34.0099, 36.2677], [60.4626, -34.1751, 39.4387], 1.2644),
+    ([63.0109, -31.0961, -5.8663], [62.8187, -29.7946, -4.0864], 1.2630),
+    ([61.2901, 3.7196, -5.3901], [61.4292, 2.2480, -4.9620], 1.8731),
+    ([35.0831, -44.1164, 3.7933], [35.0232, -40.0716, 1.5901], 1.8645),
+    ([22.7233, 20.0904, -46.6940], [23.0331, 14.9730, -42.5619], 2.0373),
+    ([36.4612, 47.8580, 18.3852], [36.2715, 50.5065, 21.2231], 1.4146),
+    ([90.8027, -2.0831, 1.4410], [91.1528, -1.6435, 0.0447], 1.4441),
+    ([90.9257, -0.5406, -0.9208], [88.6381, -0.8985, -0.7239], 1.5381),
+    ([6.7747, -0.2908, -2.4247], [5.8714, -0.0985, -2.2286], 0.6377),
+    ([2.0776, 0.0795, -1.1350], [0.9033, -0.0636, -0.5514], 0.9082),
+];
+
+#[test]
+fn ciede2000_matches_published_pairs() {
+    for (i, (lhs, rhs, expected)) in CIEDE2000_PAIRS.iter().enumerate() {
+        let delta_e = ciede2000(*lhs, *rhs);
+        assert!((delta_e - expected).abs() < 1e-4, "pair {}: {delta_e} != {expected}", i + 1);
+        // the formula is symmetric, only the order in the table differs.
+        let swapped = ciede2000(*rhs, *lhs);
+        assert!((swapped - expected).abs() < 1e-4, "pair {} swapped: {swapped} != {expected}", i + 1);
+    }
+}
+
+#[test]
+fn cie76_and_cie94_reference_values() {
+    assert_eq!(cie76([50.0, 0.0, 0.0], [50.0, 3.0, 4.0]), 5.0);
+    assert_eq!(cie76([0.0, 0.0, 0.0], [100.0, 0.0, 0.0]), 100.0);
+    // neutral reference: both weights are 1, so ΔE94 is ΔE76.
+    assert!((cie94([50.0, 0.0, 0.0], [50.0, 3.0, 4.0]) - 5.0).abs() < 1e-5);
+    // pure lightness differences are never weighted.
+    assert!((cie94([50.0, 40.0, 30.0], [60.0, 40.0, 30.0]) - 10.0).abs() < 1e-5);
+    // chroma differences are shrunk by 1 + 0.045 C*.
+    let delta_e = cie94([50.0, 50.0, 0.0], [50.0, 60.0, 0.0]);
+    assert!((delta_e - 10.0 / (1.0 + 0.045 * 50.0)).abs() < 1e-4);
+}
+
+#[test]
+fn distances_agree_across_color_spaces() {
+    let black = Rgba([0, 0, 0, 255]);
+    let white = Rgba([255, 255, 255, 255]);
+    let red = Rgba([255, 0, 0, 255]);
+    for color_space in [ColorSpace::Srgb, ColorSpace::Linear, ColorSpace::Lab, ColorSpace::Oklab, ColorSpace::Yuv] {
+        let [black, white, red] = [black, white, red].map(|c| color_space.encode(c));
+        let metric = Ciede2000::new(color_space);
+        assert_eq!(metric.distance(&red, &red), 0);
+        // ΔE00 between black and white is their ΔL of 100, and distances are scaled by 100.
+        let delta_e = metric.distance(&black, &white);
+        assert!(delta_e.abs_diff(10_000) < 150, "{color_space:?}: {delta_e}");
+        assert!(metric.distance(&black, &red) < delta_e);
+    }
+}
+
+#[test]
+fn transparent_colors_only_differ_by_alpha() {
+    let lhs = Rgba([255, 0, 0, 0]);
+    let rhs = Rgba([0, 0, 255, 0]);
+    assert_eq!(WeightedRgb::new(ColorSpace::Srgb).distance(&lhs, &rhs), 0);
+    assert_eq!(EuclideanLab::new(ColorSpace::Srgb).distance(&lhs, &rhs), 0);
+    assert_eq!(Ciede2000::new(ColorSpace::Srgb).distance(&lhs, &rhs), 0);
+    assert!(Ciede2000::new(ColorSpace::Srgb).distance(&lhs, &Rgba([255, 0, 0, 255])) > 0);
+}
@@ -0,0 +1,118 @@
+use image::{Rgba, RgbaImage};
+use imgquant::core::color_space::ColorSpace;
+use imgquant::core::rgb_helpers::cie76;
+use imgquant::{Algorithm, DitherMode, Quantizer};
+
+mod common;
+
+use common::blocks;
+
+const SPACES: [ColorSpace; 5] = [ColorSpace::Srgb, ColorSpace::Linear, ColorSpace::Lab, ColorSpace::Oklab, ColorSpace::Yuv];
+
+fn grid(alpha: u8) -> impl Iterator<Item = Rgba<u8>> {
+    (0..=255u8).step_by(5).flat_map(move |r| {
+        (0..=255u8).step_by(5).flat_map(move |g| (0..=255u8).step_by(5).map(move |b| Rgba([r, g, b, alpha])))
+    })
+}
+
+#[test]
+fn srgb_is_stored_unchanged() {
+    for rgba in grid(200) {
+        assert_eq!(ColorSpace::Srgb.encode(rgba), rgba);
+        assert_eq!(ColorSpace::Srgb.decode(rgba), rgba);
+    }
+}
+
+#[test]
+fn round_trips_stay_close() {
+    for space in SPACES {
+        // 8 bit linear light can't tell the darkest shades apart, see `ColorSpace::Linear`.
+        let tolerance = if space == ColorSpace::Linear { 7.0 } else { 2.5 };
+        for rgba in grid(77) {
+            let decoded = space.decode(space.encode(rgba));
+            assert_eq!(decoded[3], 77, "{space:?} changed alpha");
+            let delta_e = cie76(ColorSpace::Srgb.to_lab(rgba), ColorSpace::Srgb.to_lab(decoded));
+            assert!(delta_e <= tolerance, "{space:?}: {rgba:?} came back as {decoded:?}, delta E {delta_e}");
+        }
+    }
+}
+
+#[test]
+fn round_trips_settle_after_one_pass() {
+    for space in SPACES {
+        for rgba in grid(255) {
+            let encoded = space.encode(rgba);
+            let again = space.encode(space.decode(encoded));
+            for c in 0..3 {
+                assert!(encoded[c].abs_diff(again[c]) <= 1, "{space:?}: {encoded:?} became {again:?}");
+            }
+        }
+    }
+}
+
+#[test]
+fn grays_have_no_chroma() {
+    for space in [ColorSpace::Lab, ColorSpace::Oklab, ColorSpace::Yuv] {
+        for gray in (0..=255u8).step_by(15) {
+            let encoded = space.encode(Rgba([gray, gray, gray, 255]));
+            assert!(encoded[1].abs_diff(128) <= 1 && encoded[2].abs_diff(128) <= 1, "{space:?}: {gray} is {encoded:?}");
+        }
+        assert_eq!(space.encode(Rgba([0, 0, 0, 255]))[0], 0, "{space:?}");
+        assert_eq!(space.encode(Rgba([255, 255, 255, 255]))[0], 255, "{space:?}");
+    }
+}
+
+#[test]
+fn channel_weights_scale_the_distance() {
+    for space in SPACES {
+        let weights = space.channel_weights();
+        let gray = Rgba([100, 100, 100, 255]);
+        for (c, weight) in weights.into_iter().enumerate().take(3) {
+            let mut shifted = gray;
+            shifted[c] += 7;
+            assert_eq!(space.distance(&gray, &shifted), weight * 49, "{space:?} channel {c}");
+        }
+        // on black, premultiplying doesn't hide any color, so only the alpha term is left.
+        let black = Rgba([0, 0, 0, 255]);
+        assert_eq!(space.distance(&black, &Rgba([0, 0, 0, 248])), weights[3] * 49, "{space:?} alpha");
+    }
+}
+
+#[test]
+fn quantizer_palettes_come_back_as_srgb() {
+    let image = blocks(4242, 32, 32, 6);
+    for space in SPACES {
+        let tolerance = if space == ColorSpace::Linear { 7.0 } else { 2.5 };
+        for algorithm in [Algorithm::Octree, Algorithm::MedianCut] {
+            let quantized = Quantizer::new()
+                .algorithm(algorithm)
+                .color_space(space)
+                .colors(16)
+                .quantize(&image)
+                .unwrap();
+            for (source, output) in image.pixels().zip(quantized.to_rgba_image().pixels()) {
+                let delta_e = cie76(ColorSpace::Srgb.to_lab(*source), ColorSpace::Srgb.to_lab(*output));
+                assert!(delta_e <= tolerance, "{space:?} {algorithm:?}: {source:?} came back as {output:?}");
+            }
+        }
+    }
+}
+
+#[test]
+fn quantizer_matches_colors_in_its_color_space() {
+    let black = Rgba([0, 0, 0, 255]);
+    let purple = Rgba([120, 60, 200, 255]);
+    let pair = RgbaImage::from_fn(8, 8, |x, _| if x % 2 == 0 { black } else { purple });
+    // a dark blue the palette has never seen: sRGB weights put it next to black, Lab next to the purple.
+    let blue = RgbaImage::from_pixel(4, 4, Rgba([0, 0, 102, 255]));
+    for (space, expected) in [(ColorSpace::Srgb, black), (ColorSpace::Lab, purple)] {
+        let quantizer = Quantizer::new().color_space(space).dither(DitherMode::Base);
+        let mut generator = quantizer.build_generator(&pair);
+        let palette = quantizer.make_palette(&mut generator, &pair);
+        let remapped = quantizer.remap(generator.as_ref(), palette, &blue).to_rgba_image();
+        for rgba in remapped.pixels() {
+            let delta_e = cie76(ColorSpace::Srgb.to_lab(*rgba), ColorSpace::Srgb.to_lab(expected));
+            assert!(delta_e <= 2.5, "{space:?}: {rgba:?}");
+        }
+    }
+}
@@ -0,0 +1,156 @@
+//! Image fixtures and checks shared by the integration tests. Not every test uses all of them.
+#![allow(dead_code)]
+
+use std::collections::HashSet;
+
+use image::{Rgba, RgbaImage};
+use imgquant::core::fixed_palette::FixedPalette;
+use imgquant::core::palette_generator::PaletteGenerator;
+use imgquant::{DitherMode, Quantizer};
+
+/// xorshift32, so the images are the same on every run without pulling in `rand`.
+pub struct Rng(pub u32);
+
+impl Rng {
+    pub fn next(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0
+    }
+    pub fn byte(&mut self) -> u8 {
+        (self.next() >> 24) as u8
+    }
+}
+
+pub fn noise(seed: u32, width: u32, height: u32, with_alpha: bool) -> RgbaImage {
+    let mut rng = Rng(seed);
+    RgbaImage::from_fn(width, height, |_, _| {
+        let alpha = if with_alpha { rng.byte() } else { 255 };
+        Rgba([rng.byte(), rng.byte(), rng.byte(), alpha])
+    })
+}
+
+pub fn gradient(width: u32, height: u32) -> RgbaImage {
+    RgbaImage::from_fn(width, height, |x, y| {
+        Rgba([(x * 255 / width) as u8, (y * 255 / height) as u8, ((x + y) % 256) as u8, 255])
+    })
+}
+
+/// Flat blocks picked from `color_count` random colors.
+pub fn blocks(seed: u32, width: u32, height: u32, color_count: usize) -> RgbaImage {
+    let mut rng = Rng(seed);
+    let colors: Vec<Rgba<u8>> = (0..color_count)
+        .map(|_| Rgba([rng.byte(), rng.byte(), rng.byte(), 255]))
+        .collect();
+    RgbaImage::from_fn(width, height, |x, y| colors[((x / 4 + y / 4 * 7) as usize) % color_count])
+}
+
+pub fn synthetic_images() -> Vec<RgbaImage> {
+    let mut images = vec![
+        gradient(64, 48),
+        gradient(7, 3),
+        RgbaImage::from_pixel(5, 5, Rgba([12, 34, 56, 255])),
+    ];
+    for seed in 1..=6 {
+        images.push(noise(seed * 7919, 40, 30, false));
+        images.push(blocks(seed * 104729, 32, 32, seed as usize * 5));
+    }
+    images.push(blocks(3, 16, 16, 2));
+    images
+}
+
+/// Adds every pixel of `image` to `generator`.
+pub fn add_image<G: PaletteGenerator>(mut generator: G, image: &RgbaImage) -> G {
+    for rgba in image.pixels() {
+        generator.add_color(*rgba);
+    }
+    generator
+}
+
+/// Makes a palette of `color_count` out of what `generator` was given, which must have been
+/// `image`, and checks what every generator promises: no more than `color_count` entries,
+/// opaque ones unless it tracks alpha, and an entry for every added color.
+///
+/// Returns the palette and every index the pixels of `image` were given.
+pub fn checked_palette(generator: &mut impl PaletteGenerator, image: &RgbaImage, color_count: i32) -> (Vec<Rgba<u8>>, HashSet<usize>) {
+    let palette = generator.make_palette(color_count);
+    assert!(!palette.is_empty());
+    assert!(palette.len() <= color_count.max(1) as usize, "{} entries for {color_count} colors", palette.len());
+    assert_eq!(generator.color_count(), palette.len());
+    if !generator.has_alpha() {
+        assert!(palette.iter().all(|rgba| rgba.0[3] == u8::MAX));
+    }
+
+    let used = image.pixels()
+        .map(|rgba| generator.get_palette_index(*rgba, false).expect("every added color has an entry"))
+        .inspect(|&index| assert!(index < palette.len()))
+        .collect();
+    (palette, used)
+}
+
+/// Checks that the palette is exactly `distinct.min(color_count)` long, `distinct` being as many
+/// colors as the generator can tell apart in `image`, and that every entry is some pixel's.
+pub fn assert_exact_palette(mut generator: impl PaletteGenerator, image: &RgbaImage, color_count: i32, distinct: usize) {
+    let (palette, used) = checked_palette(&mut generator, image, color_count);
+    assert_eq!(palette.len(), distinct.min(color_count as usize), "{color_count} colors");
+    assert_eq!(used.len(), palette.len(), "unused entries");
+}
+
+/// Checks that images of a few flat colors get every one of them back, within `tolerance`
+/// per channel, from a fresh generator each.
+pub fn assert_few_colors_map_to_themselves<G: PaletteGenerator>(new_generator: impl Fn() -> G, tolerance: u8) {
+    for color_count in [2, 5, 12] {
+        let image = blocks(color_count as u32 * 17, 32, 32, color_count);
+        let mut generator = add_image(new_generator(), &image);
+        let palette = generator.make_palette(256);
+        for rgba in image.pixels() {
+            let entry = palette[generator.get_palette_index(*rgba, false).unwrap()];
+            let drift = entry.0.iter().zip(rgba.0).map(|(a, b)| a.abs_diff(b)).max().unwrap();
+            assert!(drift <= tolerance, "{entry:?} for {rgba:?}");
+        }
+    }
+}
+
+/// Checks that a color `generator` never saw is only found with `force_find_color`.
+pub fn assert_unseen_colors_need_force_find_color(generator: impl PaletteGenerator) {
+    let image = blocks(99, 16, 16, 4);
+    let mut generator = add_image(generator, &image);
+    let palette = generator.make_palette(16);
+    let unseen = Rgba([1, 2, 3, 255]);
+    assert!(!image.pixels().any(|rgba| *rgba == unseen));
+    assert_eq!(generator.get_palette_index(unseen, false), None);
+    assert!(generator.get_palette_index(unseen, true).unwrap() < palette.len());
+}
+
+pub const BLACK: Rgba<u8> = Rgba([0, 0, 0, 255]);
+pub const WHITE: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
+/// Checks that every flat gray `dither` maps to black and white gets a share of white
+/// within `tolerance` of its brightness. `dither` gets the generator, the palette and the image.
+pub fn assert_flat_grays_keep_their_brightness(grays: &[u8], tolerance: f32, label: &str, dither: impl Fn(&FixedPalette, &[Rgba<u8>], &RgbaImage) -> Vec<u32>) {
+    let palette = FixedPalette::new(vec![BLACK, WHITE], false);
+    for &gray in grays {
+        let image = RgbaImage::from_pixel(64, 48, Rgba([gray, gray, gray, 255]));
+        let indices = dither(&palette, &[BLACK, WHITE], &image);
+        let share = indices.iter().filter(|&&index| index == 1).count() as f32 / indices.len() as f32;
+        assert!((share - f32::from(gray) / 255.0).abs() < tolerance, "{label}, gray {gray}: {share}");
+    }
+}
+
+/// Checks that with `dither_mode`, fully transparent pixels and only those get the transparent slot.
+pub fn assert_transparent_pixels_keep_their_slot(dither_mode: DitherMode) {
+    let image = RgbaImage::from_fn(9, 6, |x, y| {
+        if (x + y) % 3 == 0 { Rgba([0, 0, 0, 0]) } else { Rgba([(x * 28) as u8, (y * 40) as u8, 90, 255]) }
+    });
+    let quantized = Quantizer::new()
+        .colors(4)
+        .transparent_slot(true)
+        .dither(dither_mode.clone())
+        .quantize(&image)
+        .unwrap();
+    let transparent_index = quantized.palette.len() as u32 - 1;
+    for (rgba, &index) in image.pixels().zip(&quantized.indices) {
+        assert_eq!(rgba.0[3] == 0, index == transparent_index, "{dither_mode:?}");
+    }
+}
@@ -0,0 +1,197 @@
+use image::{Rgba, RgbaImage};
+use imgquant::core::fixed_palette::FixedPalette;
+use imgquant::core::rgb_helpers::{nearest_color_from_palette, WeightedRgb};
+use imgquant::dither::kernel::{DiffusionKernel, KernelError, KernelTap};
+use imgquant::dither::{quantize_dither_image, DiffusionControls, ErrorDiffusion};
+use imgquant::{Algorithm, ColorSpace, DitherMode, QuantizeError, Quantizer};
+
+mod common;
+
+use common::{assert_flat_grays_keep_their_brightness, assert_transparent_pixels_keep_their_slot, BLACK, WHITE};
+
+const DIFFUSION_MODES: [DitherMode; 10] = [
+    DitherMode::FloydSteinberg,
+    DitherMode::SierraLite,
+    DitherMode::JarvisJudiceNinke,
+    DitherMode::Stucki,
+    DitherMode::Burkes,
+    DitherMode::Sierra3,
+    DitherMode::TwoRowSierra,
+    DitherMode::Atkinson,
+    DitherMode::ShiauFan,
+    DitherMode::ShiauFan2,
+];
+
+/// Dithers `image` to `colors`, returning indices into them.
+fn dither_to(colors: &[Rgba<u8>], image: &RgbaImage, diffusion: &ErrorDiffusion) -> Vec<u32> {
+    let palette = FixedPalette::new(colors.to_vec(), false);
+    quantize_dither_image(&palette, colors, image, diffusion, None, &WeightedRgb::new(ColorSpace::Srgb))
+}
+
+#[test]
+fn built_in_kernels_spread_all_of_the_error() {
+    for dither_mode in DIFFUSION_MODES {
+        let kernel = dither_mode.diffusion_kernel().unwrap();
+        let total: u32 = kernel.taps().iter().map(|tap| tap.weight).sum();
+        if dither_mode == DitherMode::Atkinson {
+            assert_eq!((total, kernel.divisor()), (6, 8));
+        } else {
+            assert_eq!(total, kernel.divisor(), "{dither_mode:?}");
+        }
+    }
+    assert!(DitherMode::Base.diffusion_kernel().is_none());
+    assert!(DitherMode::BlueNoise.diffusion_kernel().is_none());
+}
+
+#[test]
+fn kernels_parse_from_grids() {
+    assert_eq!("- * 7\n3 5 1\n/16".parse::<DiffusionKernel>().unwrap(), DiffusionKernel::floyd_steinberg());
+    assert_eq!("- * 7; 3 5 1".parse::<DiffusionKernel>().unwrap(), DiffusionKernel::floyd_steinberg());
+    let jarvis = "
+        # Jarvis, Judice and Ninke
+        - - * 7 5
+        3 5 7 5 3
+        1 3 5 3 1   # /48 is the sum anyway
+    ";
+    let jarvis = jarvis.parse::<DiffusionKernel>().unwrap();
+    assert_eq!(jarvis, DiffusionKernel::jarvis_judice_ninke());
+    assert_eq!(jarvis.rows(), 3);
+    assert_eq!("* 1 1; 1 1 0; /8".parse::<DiffusionKernel>().unwrap().taps(), [
+        KernelTap { dx: 1, dy: 0, weight: 1 },
+        KernelTap { dx: 2, dy: 0, weight: 1 },
+        KernelTap { dx: 0, dy: 1, weight: 1 },
+        KernelTap { dx: 1, dy: 1, weight: 1 },
+    ]);
+}
+
+#[test]
+fn bad_kernels_are_rejected() {
+    let parse = |spec: &str| spec.parse::<DiffusionKernel>().unwrap_err();
+    assert!(matches!(parse("- - 7; 3 5 1"), KernelError::CurrentPixel));
+    assert!(matches!(parse("- * 7; 3 * 1"), KernelError::CurrentPixel));
+    assert!(matches!(parse("- 7 -; 3 * 1"), KernelError::CurrentPixel));
+    assert!(matches!(parse("- * 7; 3 5"), KernelError::RaggedRows));
+    assert!(matches!(parse("- * x; 3 5 1"), KernelError::InvalidWeight(_)));
+    assert!(matches!(parse("2 * 7; 3 5 1"), KernelError::BackwardTap(-1, 0)));
+    assert!(matches!(parse("- * 0; 0 0 0"), KernelError::Empty));
+    assert!(matches!(parse("- * 7; 3 5 1; /0"), KernelError::ZeroDivisor));
+    assert!(matches!(DiffusionKernel::new(vec![KernelTap { dx: 0, dy: 0, weight: 1 }], 1), Err(KernelError::BackwardTap(0, 0))));
+}
+
+#[test]
+fn weights_stay_within_the_divisor() {
+    let parse = |spec: &str| spec.parse::<DiffusionKernel>();
+    assert!(matches!(parse("- * 7; 3 5 1; /15"), Err(KernelError::WeightsExceedDivisor(16, 15))));
+    assert!(matches!(parse("- * 2147483648; 1 1 1"), Err(KernelError::TooLarge(2147483648))));
+    assert!(matches!(parse("- * 1; 1 1 1; /4294967295"), Err(KernelError::TooLarge(4294967295))));
+    // the default divisor is the sum, which has to fit as well.
+    assert!(matches!(parse("- * 2147483647; 2147483647 2147483647 1"), Err(KernelError::WeightsExceedDivisor(..))));
+    let largest = parse("- * 1073741823; 1073741824 - -").unwrap();
+    assert_eq!(largest.divisor(), i32::MAX as u32);
+    // weights this large still spread the error without overflowing.
+    let image = RgbaImage::from_fn(16, 8, |x, y| Rgba([(x * 16) as u8, (y * 32) as u8, 90, 255]));
+    let quantized = Quantizer::new().colors(4).dither(DitherMode::Custom(largest)).quantize(&image).unwrap();
+    assert_eq!(quantized.indices.len(), 16 * 8);
+}
+
+#[test]
+fn flat_grays_keep_their_brightness() {
+    let custom = DitherMode::Custom("- * 2; 1 1 -".parse().unwrap());
+    for dither_mode in DIFFUSION_MODES.into_iter().chain([custom]) {
+        // atkinson drops a quarter of the error, so only a mid gray stays put.
+        let grays: &[u8] = if dither_mode == DitherMode::Atkinson { &[128] } else { &[32, 100, 128, 220] };
+        for serpentine in [false, true] {
+            let diffusion = ErrorDiffusion::new(dither_mode.diffusion_kernel().unwrap()).serpentine(serpentine);
+            assert_flat_grays_keep_their_brightness(grays, 0.02, &format!("{dither_mode:?}, serpentine {serpentine}"), |generator, palette, image| {
+                quantize_dither_image(generator, palette, image, &diffusion, None, &WeightedRgb::new(ColorSpace::Srgb))
+            });
+        }
+    }
+}
+
+#[test]
+fn transparent_pixels_keep_their_slot() {
+    for dither_mode in DIFFUSION_MODES {
+        assert_transparent_pixels_keep_their_slot(dither_mode);
+    }
+}
+
+#[test]
+fn serpentine_rows_run_backwards_with_a_mirrored_kernel() {
+    let image = RgbaImage::from_pixel(4, 2, Rgba([128, 128, 128, 255]));
+    // every bit of error goes one pixel along the scan, so each row alternates from where it starts.
+    let kernel: DiffusionKernel = "* 1".parse().unwrap();
+
+    let forward = ErrorDiffusion::new(kernel.clone());
+    let indices = dither_to(&[BLACK, WHITE], &image, &forward);
+    assert_eq!(indices, [1, 0, 1, 0, 1, 0, 1, 0]);
+
+    let serpentine = ErrorDiffusion::new(kernel).serpentine(true);
+    let indices = dither_to(&[BLACK, WHITE], &image, &serpentine);
+    assert_eq!(indices, [1, 0, 1, 0, 0, 1, 0, 1]);
+}
+
+#[test]
+fn zero_strength_maps_every_pixel_to_its_nearest_color() {
+    let image = RgbaImage::from_fn(23, 17, |x, y| Rgba([(x * 11) as u8, (y * 15) as u8, 100, 255]));
+    let diffusion = ErrorDiffusion::new(DiffusionKernel::floyd_steinberg()).controls(DiffusionControls::new().strength(0.0));
+    let indices = dither_to(&[BLACK, WHITE], &image, &diffusion);
+    let nearest: Vec<u32> = image.pixels().map(|rgba| nearest_color_from_palette(&[BLACK, WHITE], rgba, &WeightedRgb::new(ColorSpace::Srgb)) as u32).collect();
+    assert_eq!(indices, nearest);
+}
+
+#[test]
+fn clamped_error_stops_light_grays_from_dithering() {
+    // 200 needs at least 73 of error to fall below the halfway point.
+    let image = RgbaImage::from_pixel(31, 29, Rgba([200, 200, 200, 255]));
+    let diffusion = ErrorDiffusion::new(DiffusionKernel::floyd_steinberg()).controls(DiffusionControls::new().max_error(40));
+    let indices = dither_to(&[BLACK, WHITE], &image, &diffusion);
+    assert!(indices.iter().all(|&index| index == 1));
+}
+
+#[test]
+fn edge_aware_diffusion_keeps_hard_edges_clean() {
+    let light = Rgba([230, 230, 230, 255]);
+    // a gray box on white: plain diffusion leaks the box's error out as light specks around it.
+    let inside = |x: u32, y: u32| (4..20).contains(&x) && (4..20).contains(&y);
+    let image = RgbaImage::from_fn(24, 24, |x, y| if inside(x, y) { Rgba([120, 120, 120, 255]) } else { WHITE });
+    let specks = |edge_aware: bool| {
+        let diffusion = ErrorDiffusion::new(DiffusionKernel::floyd_steinberg()).controls(DiffusionControls::new().edge_aware(edge_aware));
+        let indices = dither_to(&[BLACK, light, WHITE], &image, &diffusion);
+        indices.iter().enumerate().filter(|&(i, &index)| index != 2 && !inside(i as u32 % 24, i as u32 / 24)).count()
+    };
+    assert!(specks(false) > 0);
+    assert_eq!(specks(true), 0);
+}
+
+#[test]
+fn dither_strength_must_be_a_fraction() {
+    let image = RgbaImage::from_pixel(4, 4, Rgba([10, 20, 30, 255]));
+    for strength in [-0.1, 1.5, f32::NAN] {
+        let result = Quantizer::new().algorithm(Algorithm::MedianCut).dither_strength(strength).quantize(&image);
+        assert!(matches!(result, Err(QuantizeError::InvalidDitherStrength(_))), "{strength}");
+    }
+}
+
+#[test]
+fn error_never_wraps_onto_the_next_row() {
+    // the first row ends owing 90, which would turn the 60 starting the next row white.
+    let image = RgbaImage::from_fn(3, 2, |_, y| if y == 0 { Rgba([200, 200, 200, 255]) } else { Rgba([60, 60, 60, 255]) });
+    let diffusion = ErrorDiffusion::new("* 1".parse().unwrap());
+    let indices = dither_to(&[BLACK, WHITE], &image, &diffusion);
+    assert_eq!(indices, [1, 1, 0, 0, 0, 1]);
+}
+
+#[test]
+fn tiny_images_and_piled_up_error_are_handled() {
+    // weights far past the divisor would pile up error well beyond i16 within a few rows.
+    assert!(matches!("- - * 9 9; 9 9 9 9 9; 9 9 9 9 9 /1".parse::<DiffusionKernel>(), Err(KernelError::WeightsExceedDivisor(108, 1))));
+    for linear_light in [false, true] {
+        let diffusion = ErrorDiffusion::new(DiffusionKernel::jarvis_judice_ninke()).serpentine(true).controls(DiffusionControls::new().linear_light(linear_light));
+        for (width, height) in [(1, 1), (1, 9), (9, 1), (2, 3), (12, 12)] {
+            let image = RgbaImage::from_fn(width, height, |x, y| Rgba([(x * 20 + y * 7) as u8, 90, 200, 255]));
+            let indices = dither_to(&[BLACK, WHITE], &image, &diffusion);
+            assert_eq!(indices.len(), (width * height) as usize);
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use std::rc::Rc;
+
+use image::Rgba;
+use imgquant::core::color_space::ColorSpace;
+use imgquant::core::fixed_palette::FixedPalette;
+use imgquant::core::palette_generator::PaletteGenerator;
+use imgquant::core::rgb_helpers::Cie76;
+
+mod common;
+
+use common::noise;
+
+const BLACK: Rgba<u8> = Rgba([0, 0, 0, 255]);
+const WHITE: Rgba<u8> = Rgba([255, 255, 255, 255]);
+const RED: Rgba<u8> = Rgba([220, 20, 20, 255]);
+const CLEAR_RED: Rgba<u8> = Rgba([220, 20, 20, 0]);
+
+fn palette() -> Vec<Rgba<u8>> {
+    vec![BLACK, WHITE, RED, CLEAR_RED]
+}
+
+#[test]
+fn palette_is_kept_as_given() {
+    for with_alpha in [false, true] {
+        let mut fixed = FixedPalette::new(palette(), with_alpha);
+        for rgba in noise(7, 8, 8, with_alpha).pixels() {
+            fixed.add_color(*rgba);
+        }
+        assert_eq!(fixed.color_count(), 4);
+        for color_count in [1, 2, 256] {
+            assert_eq!(fixed.make_palette(color_count), palette());
+        }
+        assert_eq!(fixed.has_alpha(), with_alpha);
+    }
+}
+
+#[test]
+fn colors_map_to_the_nearest_entry() {
+    let mut fixed = FixedPalette::new(palette(), false);
+    let colors = [(Rgba([10, 5, 0, 255]), 0), (Rgba([240, 250, 230, 255]), 1), (Rgba([200, 40, 30, 255]), 2)];
+    for (color, _) in colors {
+        fixed.add_color(color);
+    }
+    fixed.make_palette(4);
+    for (color, index) in colors {
+        assert_eq!(fixed.get_palette_index(color, false), Some(index), "{color:?}");
+    }
+}
+
+#[test]
+fn alpha_is_dropped_unless_tracked() {
+    let faded = Rgba([210, 30, 25, 10]);
+
+    let mut opaque = FixedPalette::new(palette(), false);
+    opaque.add_color(faded);
+    opaque.make_palette(4);
+    // normalized to opaque on the way in, so it lands on the opaque red.
+    assert_eq!(opaque.get_palette_index(Rgba([210, 30, 25, 255]), false), Some(2));
+
+    let mut with_alpha = FixedPalette::new(palette(), true);
+    with_alpha.add_color(faded);
+    with_alpha.make_palette(4);
+    assert_eq!(with_alpha.get_palette_index(faded, false), Some(3));
+    assert_eq!(with_alpha.get_palette_index(Rgba([210, 30, 25, 255]), true), Some(2));
+}
+
+#[test]
+fn unseen_colors_need_force_find_color() {
+    for with_alpha in [false, true] {
+        let mut fixed = FixedPalette::new(palette(), with_alpha);
+        fixed.add_color(BLACK);
+        fixed.make_palette(4);
+        assert_eq!(fixed.get_palette_index(Rgba([250, 245, 255, 255]), false), None);
+        assert_eq!(fixed.get_palette_index(Rgba([250, 245, 255, 255]), true), Some(1));
+    }
+    let empty = FixedPalette::new(Vec::new(), false);
+    assert_eq!(empty.get_palette_index(BLACK, true), None);
+}
+
+#[test]
+fn distance_picks_the_entry() {
+    // a dark blue is closer to black by weighted rgb, but in Lab it is nearer the purple.
+    let palette = vec![BLACK, Rgba([120, 60, 200, 255])];
+    let blue = Rgba([0, 0, 153, 255]);
+    let mut weighted = FixedPalette::new(palette.clone(), false);
+    let mut lab = FixedPalette::new(palette, false).distance(Rc::new(Cie76::new(ColorSpace::Srgb)));
+    weighted.add_color(blue);
+    lab.add_color(blue);
+    assert_eq!(weighted.get_palette_index(blue, false), Some(0));
+    assert_eq!(lab.get_palette_index(blue, false), Some(1));
+}
@@ -0,0 +1,226 @@
+use std::io::Cursor;
+use std::{env, fs, process::Command};
+
+use image::{Rgba, RgbaImage};
+use imgquant::export::indexed_gif::write_indexed_gif;
+use imgquant::export::indexed_png::write_indexed_png;
+use imgquant::export::{fits_indexed, ExportError};
+use imgquant::{DitherMode, QuantizedImage, Quantizer};
+use png::{BitDepth, Transformations};
+
+/// `count` colors far enough apart to get their own palette entries, cycled over the pixels.
+fn cycled_colors(width: u32, height: u32, count: u32) -> RgbaImage {
+    RgbaImage::from_fn(width, height, |x, y| {
+        let i = (y * width + x) % count;
+        Rgba([(i * 6) as u8, (255 - i * 6) as u8, (i * 37 % 256) as u8, 255])
+    })
+}
+
+/// Every pixel a different color and alpha, so (index, alpha) pairs run far past 256.
+fn partial_alpha(width: u32, height: u32) -> RgbaImage {
+    RgbaImage::from_fn(width, height, |x, y| {
+        Rgba([(x * 4) as u8, (y * 4) as u8, ((x ^ y) * 4) as u8, (x * y % 256) as u8])
+    })
+}
+
+/// Far more than 256 opaque colors around a fully transparent square.
+fn transparent_hole(width: u32, height: u32) -> RgbaImage {
+    RgbaImage::from_fn(width, height, |x, y| {
+        let hole = (width / 4..width / 2).contains(&x) && (height / 4..height / 2).contains(&y);
+        Rgba([(x * 4) as u8, (y * 4) as u8, ((x + y) * 2) as u8, if hole { 0 } else { 255 }])
+    })
+}
+
+fn quantize(image: &RgbaImage) -> QuantizedImage {
+    Quantizer::new().dither(DitherMode::Base).quantize(image).unwrap()
+}
+
+fn encode_png(quantized: &QuantizedImage) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    write_indexed_png(quantized, &mut buffer).unwrap();
+    buffer
+}
+
+fn encode_gif(quantized: &QuantizedImage) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    write_indexed_gif(quantized, &mut buffer).unwrap();
+    buffer
+}
+
+/// Writes `quantized` as a gif and reads back which pixels came out transparent.
+fn gif_transparency(quantized: &QuantizedImage) -> Vec<bool> {
+    let decoded = image::load_from_memory_with_format(&encode_gif(quantized), image::ImageFormat::Gif).unwrap().to_rgba8();
+    decoded.pixels().map(|rgba| rgba.0[3] == 0).collect()
+}
+
+/// The PNG's header info and its image data exactly as stored, still packed.
+fn decode_raw(buffer: &[u8]) -> (png::Info<'static>, Vec<u8>) {
+    let mut decoder = png::Decoder::new(Cursor::new(buffer));
+    decoder.set_transformations(Transformations::IDENTITY);
+    let mut reader = decoder.read_info().unwrap();
+    let mut data = vec![0; reader.output_buffer_size()];
+    let frame = reader.next_frame(&mut data).unwrap();
+    data.truncate(frame.buffer_size());
+    (reader.info().clone(), data)
+}
+
+#[test]
+fn bit_depth_is_the_smallest_that_fits() {
+    for (count, expected) in [(2, BitDepth::One), (3, BitDepth::Two), (4, BitDepth::Two), (5, BitDepth::Four), (16, BitDepth::Four), (17, BitDepth::Eight), (40, BitDepth::Eight)] {
+        let quantized = quantize(&cycled_colors(13, 5, count));
+        assert_eq!(quantized.palette.len(), count as usize);
+        let (info, _) = decode_raw(&encode_png(&quantized));
+        assert_eq!(info.color_type, png::ColorType::Indexed);
+        assert_eq!(info.bit_depth, expected, "{count} colors");
+    }
+}
+
+#[test]
+fn opaque_palettes_are_written_as_is() {
+    let quantized = quantize(&cycled_colors(13, 5, 40));
+    let (info, _) = decode_raw(&encode_png(&quantized));
+    let palette: Vec<u8> = quantized.palette.iter().flat_map(|rgba| rgba.0[..3].to_vec()).collect();
+    assert_eq!(info.palette.as_deref(), Some(&palette[..]));
+    assert!(info.trns.is_none());
+}
+
+#[test]
+fn sub_byte_rows_are_packed_and_padded() {
+    // odd widths, so most rows end partway through a byte.
+    for width in [1, 3, 7, 9, 13] {
+        for count in [2, 4, 16] {
+            let quantized = quantize(&cycled_colors(width, 3, count));
+            let (info, data) = decode_raw(&encode_png(&quantized));
+            let bits = info.bit_depth as usize;
+            let row_bytes = (width as usize * bits).div_ceil(8);
+            assert_eq!(data.len(), row_bytes * 3, "width {width}, {count} colors");
+
+            for (row, indices) in data.chunks(row_bytes).zip(quantized.indices.chunks(width as usize)) {
+                for (x, &index) in indices.iter().enumerate() {
+                    let bit = x * bits;
+                    let sample = (row[bit / 8] >> (8 - bits - bit % 8)) & ((1 << bits) - 1) as u8;
+                    assert_eq!(u32::from(sample), index, "width {width}, {count} colors, x {x}");
+                }
+                let used_bits = width as usize * bits;
+                if !used_bits.is_multiple_of(8) {
+                    assert_eq!(row[row_bytes - 1] & (0xff >> (used_bits % 8)), 0, "padding is zeroed");
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn translucent_entries_go_first_in_trns() {
+    let opaque = cycled_colors(13, 5, 4);
+    let image = RgbaImage::from_fn(13, 5, |x, y| {
+        let [r, g, b, _] = opaque.get_pixel(x, y).0;
+        Rgba([r, g, b, [255, 0, 128, 255][(x % 4) as usize]])
+    });
+    let quantized = quantize(&image);
+    let buffer = encode_png(&quantized);
+
+    let (info, _) = decode_raw(&buffer);
+    let palette_len = info.palette.as_ref().unwrap().len() / 3;
+    let trns = info.trns.as_deref().unwrap();
+    assert!(!trns.is_empty() && trns.len() < palette_len);
+    assert!(trns.iter().all(|&a| a < 255), "tRNS stops at the last translucent entry");
+
+    let decoded = image::load_from_memory_with_format(&buffer, image::ImageFormat::Png).unwrap().to_rgba8();
+    assert_eq!(decoded, quantized.to_rgba_image());
+}
+
+#[test]
+fn gif_keeps_the_palette_and_indices() {
+    let quantized = quantize(&cycled_colors(13, 5, 40));
+    let buffer = encode_gif(&quantized);
+
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::Indexed);
+    let mut decoder = options.read_info(Cursor::new(buffer)).unwrap();
+    let palette: Vec<u8> = quantized.palette.iter().flat_map(|rgba| rgba.0[..3].to_vec()).collect();
+    assert_eq!(&decoder.global_palette().unwrap()[..palette.len()], &palette[..]);
+    let frame = decoder.read_next_frame().unwrap().unwrap();
+    assert_eq!(frame.transparent, None);
+    assert!(frame.buffer.iter().map(|&index| u32::from(index)).eq(quantized.indices.iter().copied()));
+}
+
+#[test]
+fn gif_transparency_follows_alpha() {
+    let opaque = cycled_colors(13, 5, 6);
+    let image = RgbaImage::from_fn(13, 5, |x, y| {
+        let [r, g, b, _] = opaque.get_pixel(x, y).0;
+        Rgba([r, g, b, [0, 100, 200, 255][(x % 4) as usize]])
+    });
+    let quantized = quantize(&image);
+    let decoded = image::load_from_memory_with_format(&encode_gif(&quantized), image::ImageFormat::Gif).unwrap().to_rgba8();
+
+    // gif has no partial transparency, alpha is cut at 128.
+    for ((decoded, expected), source) in decoded.pixels().zip(quantized.to_rgba_image().pixels()).zip(image.pixels()) {
+        if source.0[3] < 128 {
+            assert_eq!(decoded.0[3], 0);
+        } else {
+            assert_eq!(decoded.0, [expected.0[0], expected.0[1], expected.0[2], 255]);
+        }
+    }
+}
+
+#[test]
+fn gif_dimensions_are_limited() {
+    let quantized = quantize(&cycled_colors(70_000, 1, 2));
+    let result = write_indexed_gif(&quantized, Vec::new());
+    assert!(matches!(result, Err(ExportError::TooLarge(70_000, 1))));
+}
+
+#[test]
+fn partial_alpha_doesnt_fit_an_indexed_png() {
+    let quantized = Quantizer::new().quantize(&partial_alpha(64, 64)).unwrap();
+    assert!(quantized.alpha.is_some());
+    assert!(!fits_indexed(&quantized));
+    let result = write_indexed_png(&quantized, Vec::new());
+    assert!(matches!(result, Err(ExportError::TooManyColors(count)) if count > 256));
+
+    let opaque = Quantizer::new().quantize(&RgbaImage::from_fn(64, 64, |x, y| Rgba([x as u8, y as u8, 0, 255]))).unwrap();
+    assert!(fits_indexed(&opaque));
+}
+
+#[test]
+fn cli_saves_partial_alpha_pngs_in_full_color() {
+    let dir = env::temp_dir().join(format!("imgquant_export_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let (source, dest) = (dir.join("partial_alpha.png"), dir.join("partial_alpha_out.png"));
+    partial_alpha(64, 64).save(&source).unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_imgquant"))
+        .args(["-i", source.to_str().unwrap(), "-o", dest.to_str().unwrap()])
+        .output()
+        .unwrap()
+        .status;
+    assert!(status.success());
+    let saved = image::open(&dest).expect("the png is written even though it can't be indexed").to_rgba8();
+    assert_eq!(saved.dimensions(), (64, 64));
+    assert!(saved.pixels().zip(partial_alpha(64, 64).pixels()).all(|(saved, source)| saved.0[3] == source.0[3]));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn gif_reuses_the_transparent_slot_at_256_colors() {
+    let image = transparent_hole(64, 64);
+    let quantized = Quantizer::new().colors(256).transparent_slot(true).quantize(&image).unwrap();
+    assert_eq!(quantized.palette.len(), 256);
+    let expected: Vec<bool> = image.pixels().map(|rgba| rgba.0[3] == 0).collect();
+    assert_eq!(gif_transparency(&quantized), expected);
+}
+
+#[test]
+fn gif_with_alpha_palettes_fits_256_colors() {
+    let image = partial_alpha(64, 64);
+    let quantized = Quantizer::new().colors(256).alpha(true).quantize(&image).unwrap();
+    assert_eq!(quantized.palette.len(), 256);
+    assert!(quantized.alpha.is_none());
+    // every pixel whose entry is mostly transparent shares the one transparent index.
+    let expected: Vec<bool> = quantized.indices.iter().map(|&index| quantized.palette[index as usize].0[3] < 128).collect();
+    assert!(expected.iter().any(|&transparent| transparent));
+    assert_eq!(gif_transparency(&quantized), expected);
+}
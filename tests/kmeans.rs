@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+
+use image::{Rgba, RgbaImage};
+use imgquant::core::color_space::ColorSpace;
+use imgquant::core::kmeans::refine_palette;
+use imgquant::core::rgb_helpers::{nearest_color_from_palette, ColorDistance, WeightedRgb};
+
+mod common;
+
+use common::{blocks, noise};
+
+fn histogram(image: &RgbaImage) -> Vec<(Rgba<u8>, u32)> {
+    let mut counts = HashMap::new();
+    for rgba in image.pixels() {
+        *counts.entry(*rgba).or_insert(0) += 1;
+    }
+    let mut histogram: Vec<_> = counts.into_iter().collect();
+    histogram.sort_unstable_by_key(|(color, _)| color.0);
+    histogram
+}
+
+fn total_error(palette: &[Rgba<u8>], histogram: &[(Rgba<u8>, u32)], distance: &dyn ColorDistance) -> u64 {
+    histogram.iter()
+        .map(|(color, count)| {
+            let nearest = palette[nearest_color_from_palette(palette, color, distance)];
+            u64::from(distance.distance(color, &nearest)) * u64::from(*count)
+        })
+        .sum()
+}
+
+/// A gray ramp, so the seeds are far from most of the image's colors.
+fn gray_seeds(count: usize) -> Vec<Rgba<u8>> {
+    (0..count).map(|i| {
+        let gray = (i * 255 / (count - 1)) as u8;
+        Rgba([gray, gray, gray, 255])
+    }).collect()
+}
+
+#[test]
+fn refinement_stops_early_and_lowers_the_error() {
+    let distance = WeightedRgb::new(ColorSpace::Srgb);
+    for (image, seed_count) in [(blocks(7, 32, 32, 6), 6), (noise(1234, 40, 30, false), 16)] {
+        let histogram = histogram(&image);
+        let mut palette = gray_seeds(seed_count);
+        let before = total_error(&palette, &histogram, &distance);
+
+        let iterations = refine_palette(&mut palette, &histogram, 100, 0, &distance);
+        let after = total_error(&palette, &histogram, &distance);
+
+        assert!((1..100).contains(&iterations), "{iterations} iterations");
+        assert!(after < before, "error went from {before} to {after}");
+        // a converged palette doesn't move any further.
+        let converged = palette.clone();
+        assert_eq!(refine_palette(&mut palette, &histogram, 100, 0, &distance), 1);
+        assert_eq!(palette, converged);
+    }
+}
+
+#[test]
+fn flat_colors_are_recovered_exactly() {
+    let distance = WeightedRgb::new(ColorSpace::Srgb);
+    let image = blocks(42, 32, 32, 4);
+    let histogram = histogram(&image);
+    let mut palette: Vec<Rgba<u8>> = histogram.iter().map(|(color, _)| *color).collect();
+    // nudge every seed off its color, k-means has to pull them back.
+    for entry in &mut palette {
+        entry.0[0] = entry.0[0].saturating_add(9);
+    }
+    refine_palette(&mut palette, &histogram, 100, 0, &distance);
+    assert_eq!(total_error(&palette, &histogram, &distance), 0);
+}
+
+#[test]
+fn iteration_limits_are_kept() {
+    let distance = WeightedRgb::new(ColorSpace::Srgb);
+    let histogram = histogram(&noise(99, 24, 24, false));
+
+    let mut palette = gray_seeds(8);
+    assert_eq!(refine_palette(&mut palette, &histogram, 0, 0, &distance), 0);
+    assert_eq!(palette, gray_seeds(8));
+
+    assert_eq!(refine_palette(&mut palette, &histogram, 2, 0, &distance), 2);
+    // any move is under the threshold, so one pass is enough.
+    assert_eq!(refine_palette(&mut gray_seeds(8), &histogram, 100, u32::MAX, &distance), 1);
+}
@@ -0,0 +1,49 @@
+use image::{Rgba, RgbaImage};
+use imgquant::{ColorSpace, DitherMode, QuantizeError, Quantizer};
+
+const BLACK: Rgba<u8> = Rgba([0, 0, 0, 255]);
+const WHITE: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
+fn checkerboard() -> RgbaImage {
+    RgbaImage::from_fn(8, 8, |x, y| if (x + y) % 2 == 0 { BLACK } else { WHITE })
+}
+
+#[test]
+fn black_and_white_average_to_half_the_light() {
+    for (linear_light, expected) in [(false, 127), (true, 187)] {
+        // one color plus the transparent slot, so black and white share a leaf.
+        let quantized = Quantizer::new()
+            .linear_light(linear_light)
+            .colors(2)
+            .transparent_slot(true)
+            .quantize(&checkerboard())
+            .unwrap();
+        let [r, g, b, a] = quantized.palette[0].0;
+        assert_eq!((r, g, b, a), (expected, expected, expected, 255), "linear light {linear_light}");
+    }
+}
+
+#[test]
+fn diffusion_keeps_the_light_of_a_midtone() {
+    // sRGB 128 is about 22% of white's light.
+    let gray = RgbaImage::from_pixel(32, 32, Rgba([128, 128, 128, 255]));
+    for (linear_light, expected) in [(false, 0.5), (true, 0.22)] {
+        let quantizer = Quantizer::new().linear_light(linear_light).dither(DitherMode::FloydSteinberg);
+        let mut generator = quantizer.build_generator(&checkerboard());
+        let palette = quantizer.make_palette(&mut generator, &checkerboard());
+        let dithered = quantizer.remap(generator.as_ref(), palette, &gray).to_rgba_image();
+
+        let white = dithered.pixels().filter(|rgba| **rgba == WHITE).count() as f32 / 1024.0;
+        assert!(dithered.pixels().all(|rgba| *rgba == WHITE || *rgba == BLACK));
+        assert!((white - expected).abs() < 0.02, "linear light {linear_light}: {white} white");
+    }
+}
+
+#[test]
+fn linear_light_needs_srgb() {
+    for color_space in [ColorSpace::Linear, ColorSpace::Lab, ColorSpace::Oklab, ColorSpace::Yuv] {
+        let result = Quantizer::new().linear_light(true).color_space(color_space).quantize(&checkerboard());
+        assert!(matches!(result, Err(QuantizeError::LinearLightNeedsSrgb(space)) if space == color_space));
+    }
+    assert!(Quantizer::new().linear_light(true).color_space(ColorSpace::Srgb).quantize(&checkerboard()).is_ok());
+}
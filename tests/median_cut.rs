@@ -0,0 +1,68 @@
+use std::collections::HashSet;
+
+use image::{Rgba, RgbaImage};
+use imgquant::core::median_cut::MedianCut;
+use imgquant::core::palette_generator::PaletteGenerator;
+
+mod common;
+
+use common::{add_image, assert_exact_palette, assert_few_colors_map_to_themselves, assert_unseen_colors_need_force_find_color, noise, synthetic_images};
+
+fn unique_colors(image: &RgbaImage, median_cut: &MedianCut) -> usize {
+    image.pixels().map(|rgba| median_cut.normalize(*rgba)).collect::<HashSet<_>>().len()
+}
+
+#[test]
+fn palette_is_exactly_min_of_count_and_colors() {
+    for image in synthetic_images() {
+        for color_count in [1, 2, 3, 7, 16, 64, 256, 1000] {
+            let median_cut = add_image(MedianCut::new(), &image);
+            let unique = unique_colors(&image, &median_cut);
+            assert_eq!(median_cut.color_count(), unique);
+            assert_exact_palette(median_cut, &image, color_count, unique);
+        }
+    }
+}
+
+#[test]
+fn palette_is_exact_with_alpha() {
+    for seed in 1..=4 {
+        let image = noise(seed * 31337, 24, 24, true);
+        for color_count in [2, 5, 16, 100, 1000] {
+            let median_cut = add_image(MedianCut::with_alpha(), &image);
+            let unique = unique_colors(&image, &median_cut);
+            assert_exact_palette(median_cut, &image, color_count, unique);
+        }
+    }
+}
+
+#[test]
+fn few_colors_map_to_themselves() {
+    assert_few_colors_map_to_themselves(MedianCut::new, 0);
+    let translucent = [Rgba([200, 10, 10, 60]), Rgba([200, 10, 10, 255]), Rgba([0, 90, 250, 0])];
+    let image = RgbaImage::from_fn(6, 6, |x, _| translucent[x as usize % 3]);
+    let mut median_cut = add_image(MedianCut::with_alpha(), &image);
+    let palette = median_cut.make_palette(16);
+    for rgba in translucent {
+        assert_eq!(palette[median_cut.get_palette_index(rgba, false).unwrap()], rgba);
+    }
+}
+
+#[test]
+fn unseen_colors_need_force_find_color() {
+    assert_unseen_colors_need_force_find_color(MedianCut::new());
+    let image = RgbaImage::from_fn(2, 1, |x, _| if x == 0 { Rgba([0, 0, 0, 255]) } else { Rgba([255, 255, 255, 255]) });
+    let mut median_cut = add_image(MedianCut::new(), &image);
+    let palette = median_cut.make_palette(4);
+    assert_eq!(palette[median_cut.get_palette_index(Rgba([30, 20, 10, 255]), true).unwrap()], Rgba([0, 0, 0, 255]));
+}
+
+#[test]
+fn boxes_split_along_their_widest_channel() {
+    // green runs over the whole range, red only flips between 0 and 60.
+    let image = RgbaImage::from_fn(16, 4, |x, _| Rgba([(x % 2 * 60) as u8, (x * 16) as u8, 0, 255]));
+    let mut median_cut = add_image(MedianCut::new(), &image);
+    let palette = median_cut.make_palette(2);
+    // halves of green, at the median, each still holding both reds.
+    assert_eq!(palette, [Rgba([30, 56, 0, 255]), Rgba([30, 184, 0, 255])]);
+}
@@ -0,0 +1,87 @@
+use std::collections::HashSet;
+
+use image::{Rgb, Rgba, RgbaImage};
+use imgquant::core::accum_octree::get_color_index;
+use imgquant::core::palette_generator::PaletteGenerator;
+use imgquant::morton::code::{child_index, decode, encode};
+use imgquant::morton::octree::MortonOctree;
+use imgquant::{Algorithm, DitherMode, Quantizer};
+
+mod common;
+
+use common::{add_image, assert_exact_palette, assert_few_colors_map_to_themselves, assert_unseen_colors_need_force_find_color, noise, Rng};
+
+fn build_tree(image: &RgbaImage, depth: usize) -> MortonOctree {
+    add_image(MortonOctree::new(depth), image)
+}
+
+#[test]
+fn codes_follow_the_octree() {
+    let mut rng = Rng(4242);
+    for _ in 0..1000 {
+        let color = Rgb([rng.byte(), rng.byte(), rng.byte()]);
+        let code = encode(color);
+        assert!(code < 1 << 24);
+        assert_eq!(decode(code), color);
+        for level in 0..8 {
+            assert_eq!(child_index(code, level), get_color_index(color, level), "{color:?} at {level}");
+        }
+    }
+    assert_eq!(encode(Rgb([255, 255, 255])), (1 << 24) - 1);
+    assert_eq!(encode(Rgb([128, 0, 0])), 0b100 << 21);
+}
+
+#[test]
+fn palette_is_exactly_min_of_count_and_colors() {
+    for seed in 1..=4 {
+        let image = noise(seed * 7919, 40, 30, false);
+        let unique: HashSet<Rgba<u8>> = image.pixels().copied().collect();
+        for color_count in [1, 2, 7, 16, 64, 256, 5000] {
+            let tree = build_tree(&image, 8);
+            assert_eq!(tree.color_count(), unique.len());
+            assert_exact_palette(tree, &image, color_count, unique.len());
+        }
+    }
+}
+
+#[test]
+fn few_colors_map_to_themselves() {
+    assert_few_colors_map_to_themselves(|| MortonOctree::new(8), 0);
+    // at depth 6 too, as long as they fall into different blocks.
+    let colors = [Rgba([10, 200, 30, 255]), Rgba([250, 250, 250, 255]), Rgba([0, 0, 0, 255]), Rgba([128, 64, 200, 255])];
+    let image = RgbaImage::from_fn(16, 16, |x, y| colors[((x / 4 + y / 4) % 4) as usize]);
+    let mut tree = build_tree(&image, 6);
+    let palette = tree.make_palette(16);
+    assert_eq!(palette.len(), colors.len());
+    for color in colors {
+        assert_eq!(palette[tree.get_palette_index(color, false).unwrap()], color);
+    }
+}
+
+#[test]
+fn unseen_colors_find_the_nearest_entry() {
+    assert_unseen_colors_need_force_find_color(MortonOctree::new(8));
+    let image = RgbaImage::from_fn(2, 1, |x, _| if x == 0 { Rgba([0, 0, 0, 255]) } else { Rgba([255, 255, 255, 255]) });
+    let mut tree = build_tree(&image, 6);
+    let palette = tree.make_palette(4);
+    // at depth 6, the two low bits of every channel still fall into black's block.
+    assert_eq!(palette[tree.get_palette_index(Rgba([3, 2, 1, 255]), false).unwrap()], Rgba([0, 0, 0, 255]));
+    let red = Rgba([200, 30, 30, 255]);
+    assert_eq!(tree.get_palette_index(red, false), None);
+    assert_eq!(palette[tree.get_palette_index(red, true).unwrap()], Rgba([0, 0, 0, 255]));
+}
+
+#[test]
+fn quantizer_runs_with_the_morton_backend() {
+    let image = noise(99, 48, 32, false);
+    for dither_mode in [DitherMode::Base, DitherMode::FloydSteinberg, DitherMode::Knoll] {
+        let quantized = Quantizer::new()
+            .algorithm(Algorithm::Morton)
+            .colors(16)
+            .dither(dither_mode)
+            .quantize(&image)
+            .unwrap();
+        assert!(quantized.palette.len() <= 16);
+        assert!(quantized.indices.iter().all(|&index| (index as usize) < quantized.palette.len()));
+    }
+}
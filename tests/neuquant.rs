@@ -0,0 +1,82 @@
+use std::collections::HashSet;
+
+use image::{Rgba, RgbaImage};
+use imgquant::core::neuquant::NeuQuant;
+use imgquant::core::palette_generator::PaletteGenerator;
+
+mod common;
+
+use common::{add_image, assert_few_colors_map_to_themselves, assert_unseen_colors_need_force_find_color, checked_palette, noise, synthetic_images};
+
+// the map only converges towards the pixels, each channel may end up a few steps off.
+const TOLERANCE: u8 = 4;
+
+fn train(image: &RgbaImage, sample_factor: usize, with_alpha: bool) -> NeuQuant {
+    add_image(if with_alpha { NeuQuant::with_alpha(sample_factor) } else { NeuQuant::new(sample_factor) }, image)
+}
+
+fn assert_valid_palette(image: &RgbaImage, sample_factor: usize, with_alpha: bool, color_count: i32) {
+    let (palette, _) = checked_palette(&mut train(image, sample_factor, with_alpha), image, color_count);
+    // neurons that land on the same color are merged.
+    assert_eq!(palette.iter().collect::<HashSet<_>>().len(), palette.len(), "duplicate entries");
+}
+
+#[test]
+fn palette_fits_and_has_no_duplicates() {
+    for image in synthetic_images() {
+        for sample_factor in [1, 10] {
+            for color_count in [1, 2, 16, 64, 256] {
+                assert_valid_palette(&image, sample_factor, false, color_count);
+            }
+        }
+    }
+}
+
+#[test]
+fn palette_fits_with_alpha() {
+    for seed in 1..=4 {
+        let image = noise(seed * 31337, 24, 24, true);
+        for color_count in [2, 16, 100] {
+            assert_valid_palette(&image, 1, true, color_count);
+        }
+    }
+}
+
+#[test]
+fn few_colors_map_close_to_themselves() {
+    assert_few_colors_map_to_themselves(|| NeuQuant::new(1), TOLERANCE);
+}
+
+#[test]
+fn unseen_colors_need_force_find_color() {
+    assert_unseen_colors_need_force_find_color(NeuQuant::new(1));
+}
+
+#[test]
+fn sample_factor_is_clamped_to_1_through_30() {
+    let image = noise(7, 64, 48, false);
+    for (sample_factor, clamped) in [(0, 1), (31, 30), (1000, 30)] {
+        assert_eq!(train(&image, sample_factor, false).make_palette(16), train(&image, clamped, false).make_palette(16), "{sample_factor}");
+    }
+    // and within that range, it does change what gets learned.
+    assert_ne!(train(&image, 1, false).make_palette(16), train(&image, 30, false).make_palette(16));
+}
+
+#[test]
+fn small_images_are_learned_whole() {
+    let red = Rgba([220, 20, 30, 255]);
+    let blue = Rgba([10, 40, 230, 255]);
+    let image = RgbaImage::from_fn(3, 3, |x, y| if (x + y) % 2 == 0 { red } else { blue });
+    let palette = train(&image, 1, false).make_palette(4);
+    for color in [red, blue] {
+        let closest = palette.iter()
+            .map(|entry| entry.0.iter().zip(color.0).map(|(a, b)| a.abs_diff(b)).max().unwrap())
+            .min()
+            .unwrap();
+        assert!(closest <= TOLERANCE, "{color:?} is missing from {palette:?}");
+    }
+    // fewer pixels than the sample factor would have learned nothing at all.
+    for sample_factor in [10, 30] {
+        assert_eq!(train(&image, sample_factor, false).make_palette(4), palette, "sample factor {sample_factor}");
+    }
+}
@@ -0,0 +1,150 @@
+use std::collections::HashSet;
+
+use image::{Rgba, RgbaImage};
+use imgquant::core::accum_octree::LeafOctree;
+use imgquant::{Algorithm, DitherMode, QuantizeError, Quantizer};
+
+mod common;
+
+use common::{add_image, assert_few_colors_map_to_themselves, assert_unseen_colors_need_force_find_color, gradient, noise, synthetic_images};
+
+fn build_tree(image: &RgbaImage, depth: usize, with_alpha: bool) -> LeafOctree {
+    add_image(if with_alpha { LeafOctree::with_alpha(depth) } else { LeafOctree::new(depth) }, image)
+}
+
+fn assert_exact_palette(image: &RgbaImage, depth: usize, with_alpha: bool, color_count: i32) {
+    let tree = build_tree(image, depth, with_alpha);
+    let leaf_count = tree.get_leaf_nodes().len();
+    // every leaf holds pixels, so every index is reached, which rules out stale duplicates.
+    common::assert_exact_palette(tree, image, color_count, leaf_count);
+}
+
+#[test]
+fn palette_is_exactly_min_of_count_and_leaves() {
+    for image in synthetic_images() {
+        for depth in [3, 5, 6, 8] {
+            for color_count in [1, 2, 3, 7, 16, 64, 255, 256, 1000] {
+                assert_exact_palette(&image, depth, false, color_count);
+            }
+        }
+    }
+}
+
+#[test]
+fn palette_is_exact_with_alpha() {
+    for seed in 1..=4 {
+        let image = noise(seed * 31337, 24, 24, true);
+        for color_count in [2, 5, 16, 100] {
+            assert_exact_palette(&image, 6, true, color_count);
+        }
+    }
+}
+
+#[test]
+fn leaves_at_depth_8_are_unique_colors() {
+    for image in synthetic_images() {
+        let unique: HashSet<Rgba<u8>> = image.pixels().copied().collect();
+        let tree = build_tree(&image, 8, false);
+        assert_eq!(tree.get_leaf_nodes().len(), unique.len());
+    }
+}
+
+#[test]
+fn few_colors_map_to_themselves() {
+    assert_few_colors_map_to_themselves(|| LeafOctree::new(8), 0);
+}
+
+#[test]
+fn unseen_colors_need_force_find_color() {
+    assert_unseen_colors_need_force_find_color(LeafOctree::new(8));
+}
+
+#[test]
+fn reductions_come_out_as_before() {
+    let mut tree = build_tree(&gradient(16, 8), 4, false);
+    let palette = tree.make_palette(6);
+    assert_eq!(palette.iter().map(|rgba| rgba.0).collect::<Vec<_>>(), [
+        [191, 191, 18, 255], [63, 111, 7, 255], [31, 31, 3, 255], [103, 31, 7, 255], [63, 191, 10, 255], [191, 63, 14, 255],
+    ]);
+    let first_row: Vec<usize> = gradient(16, 8).rows().next().unwrap().map(|rgba| tree.get_palette_index(*rgba, false).unwrap()).collect();
+    assert_eq!(first_row, [2, 2, 2, 2, 2, 3, 3, 3, 3, 5, 5, 5, 5, 5, 5, 5]);
+
+    let mut tree = build_tree(&noise(271828, 8, 8, true), 3, true);
+    let palette = tree.make_palette(4);
+    assert_eq!(palette.iter().map(|rgba| rgba.0).collect::<Vec<_>>(), [
+        [119, 130, 110, 99], [195, 38, 197, 46], [51, 216, 151, 212], [211, 95, 203, 199],
+    ]);
+}
+
+#[test]
+fn quantizer_indices_stay_in_palette() {
+    for image in synthetic_images() {
+        for colors in [2, 16, 64] {
+            for transparent_slot in [false, true] {
+                let quantized = Quantizer::new()
+                    .algorithm(Algorithm::Octree)
+                    .colors(colors)
+                    .transparent_slot(transparent_slot)
+                    .quantize(&image)
+                    .unwrap();
+                assert!(quantized.palette.len() <= colors as usize);
+                assert!(quantized.indices.iter().all(|&index| (index as usize) < quantized.palette.len()));
+            }
+        }
+    }
+}
+
+#[test]
+fn merged_trees_match_one_tree() {
+    for with_alpha in [false, true] {
+        for linear_light in [false, true] {
+            let image = noise(31337, 45, 27, with_alpha);
+            let pixels: Vec<Rgba<u8>> = image.pixels().copied().collect();
+            let new_tree = || {
+                let tree = if with_alpha { LeafOctree::with_alpha(6) } else { LeafOctree::new(6) };
+                tree.linear_light(linear_light)
+            };
+            let mut whole = new_tree();
+            pixels.iter().for_each(|rgba| whole.add_color(*rgba));
+            // uneven parts, merged back to front.
+            let mut merged = new_tree();
+            for part in [&pixels[700..], &pixels[13..700], &pixels[..13]] {
+                let mut tree = new_tree();
+                part.iter().for_each(|rgba| tree.add_color(*rgba));
+                merged.merge(&tree);
+            }
+            assert_eq!(merged.make_palette(32), whole.make_palette(32), "alpha {with_alpha}, linear light {linear_light}");
+            for rgba in image.pixels().chain(noise(5, 8, 8, with_alpha).pixels()) {
+                assert_eq!(merged.get_palette_index(*rgba, true), whole.get_palette_index(*rgba, true));
+            }
+        }
+    }
+}
+
+#[test]
+fn thread_count_doesnt_change_the_output() {
+    let images = [noise(2024, 41, 23, true), gradient(7, 3), RgbaImage::new(0, 0)];
+    for image in &images {
+        for with_alpha in [false, true] {
+            let quantizer = Quantizer::new()
+                .algorithm(Algorithm::Octree)
+                .colors(16)
+                .dither(DitherMode::FloydSteinberg)
+                .alpha(with_alpha)
+                .transparent_slot(!with_alpha);
+            let single = quantizer.clone().quantize(image).unwrap();
+            for threads in [2, 3, 8, 100] {
+                let parallel = quantizer.clone().threads(threads).quantize(image).unwrap();
+                assert_eq!(parallel.palette, single.palette, "{threads} threads");
+                assert_eq!(parallel.indices, single.indices, "{threads} threads");
+                assert_eq!(parallel.alpha, single.alpha, "{threads} threads");
+            }
+        }
+    }
+}
+
+#[test]
+fn zero_threads_are_rejected() {
+    let result = Quantizer::new().threads(0).quantize(&gradient(4, 4));
+    assert!(matches!(result, Err(QuantizeError::InvalidThreadCount)));
+}
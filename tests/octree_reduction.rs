@@ -0,0 +1,66 @@
+use image::{Rgba, RgbaImage};
+use imgquant::core::accum_octree::LeafOctree;
+
+mod common;
+
+use common::{noise, Rng};
+
+fn build_tree(pixels: &[Rgba<u8>], depth: usize, with_alpha: bool) -> LeafOctree {
+    let mut tree = if with_alpha { LeafOctree::with_alpha(depth) } else { LeafOctree::new(depth) };
+    for rgba in pixels {
+        tree.add_color(*rgba);
+    }
+    tree
+}
+
+fn sorted(mut palette: Vec<Rgba<u8>>) -> Vec<Rgba<u8>> {
+    palette.sort_unstable_by_key(|rgba| rgba.0);
+    palette
+}
+
+#[test]
+fn insertion_order_doesnt_matter() {
+    for with_alpha in [false, true] {
+        let image: RgbaImage = noise(8675309, 40, 30, with_alpha);
+        let pixels: Vec<Rgba<u8>> = image.pixels().copied().collect();
+        let mut shuffled = pixels.clone();
+        let mut rng = Rng(42);
+        for i in (1..shuffled.len()).rev() {
+            shuffled.swap(i, rng.next() as usize % (i + 1));
+        }
+        let reversed: Vec<Rgba<u8>> = pixels.iter().rev().copied().collect();
+
+        for color_count in [1, 7, 64, 255] {
+            let mut ordered = build_tree(&pixels, 5, with_alpha);
+            let palette = ordered.make_palette(color_count);
+            for other in [&shuffled, &reversed] {
+                let mut tree = build_tree(other, 5, with_alpha);
+                assert_eq!(tree.make_palette(color_count), palette, "alpha {with_alpha}, {color_count} colors");
+                for rgba in &pixels {
+                    assert_eq!(tree.get_palette_index(*rgba, true), ordered.get_palette_index(*rgba, true));
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn cheapest_merge_goes_first() {
+    // two pairs of siblings at depth 3, both 32 apart in one channel:
+    //   dark pair:  3 x (0, 0, 0) and 1 x (32, 0, 0)
+    //   light pair: 1 x (128, 128, 128) and 1 x (160, 128, 128)
+    // each pair's first child moves into its empty parent for free. merging the second one
+    // then costs n1 * n2 / (n1 + n2) * 32^2: 3/4 * 1024 = 768 for the dark pair,
+    // 1/2 * 1024 = 512 for the light pair, so the light pair goes first.
+    let dark = Rgba([0, 0, 0, 255]);
+    let dark_red = Rgba([32, 0, 0, 255]);
+    let light = Rgba([128, 128, 128, 255]);
+    let light_red = Rgba([160, 128, 128, 255]);
+    let pixels = [dark, light_red, dark, light, dark_red, dark];
+
+    let mut tree = build_tree(&pixels, 3, false);
+    assert_eq!(sorted(tree.make_palette(3)), sorted(vec![dark, dark_red, Rgba([144, 128, 128, 255])]));
+
+    let mut tree = build_tree(&pixels, 3, false);
+    assert_eq!(sorted(tree.make_palette(2)), sorted(vec![Rgba([8, 0, 0, 255]), Rgba([144, 128, 128, 255])]));
+}
@@ -0,0 +1,115 @@
+use std::collections::HashSet;
+
+use image::{GrayImage, Luma, Rgba, RgbaImage};
+use imgquant::core::fixed_palette::FixedPalette;
+use imgquant::core::rgb_helpers::WeightedRgb;
+use imgquant::dither::blue_noise::void_and_cluster;
+use imgquant::dither::ordered::{bayer_matrix, is_bayer_size, ordered_quantize, palette_spread, ThresholdMap};
+use imgquant::{ColorSpace, DitherMode, QuantizeError, Quantizer};
+
+#[test]
+fn bayer_4_is_the_classic_matrix() {
+    assert_eq!(bayer_matrix(2).unwrap(), [0, 2, 3, 1]);
+    assert_eq!(bayer_matrix(4).unwrap(), [
+        0, 8, 2, 10,
+        12, 4, 14, 6,
+        3, 11, 1, 9,
+        15, 7, 13, 5,
+    ]);
+}
+
+#[test]
+fn bayer_matrices_hold_every_level_once() {
+    for size in [2, 3, 4, 6, 8, 12, 16, 24, 32, 64, 256] {
+        assert!(is_bayer_size(size));
+        let matrix = bayer_matrix(size).unwrap();
+        let levels: HashSet<u32> = matrix.iter().copied().collect();
+        assert_eq!(matrix.len(), size * size);
+        assert_eq!(levels, (0..(size * size) as u32).collect());
+    }
+    for size in [0, 1, 5, 7, 9, 10, 18, 512] {
+        assert!(!is_bayer_size(size));
+        assert!(bayer_matrix(size).is_none());
+    }
+}
+
+#[test]
+fn palette_spread_is_nearest_neighbour_distance() {
+    assert_eq!(palette_spread(&[Rgba([1, 2, 3, 255])]), 0.0);
+    let palette = [Rgba([0, 0, 0, 255]), Rgba([30, 40, 0, 255]), Rgba([255, 255, 255, 255])];
+    let far = ((225.0f32 * 225.0) + (215.0 * 215.0) + (255.0 * 255.0)).sqrt();
+    assert!((palette_spread(&palette) - (50.0 + 50.0 + far) / 3.0).abs() < 1e-3);
+}
+
+#[test]
+fn flat_grays_dither_to_their_share_of_black_and_white() {
+    let black = Rgba([0, 0, 0, 255]);
+    let white = Rgba([255, 255, 255, 255]);
+    let palette = FixedPalette::new(vec![black, white], false);
+    let distance = WeightedRgb::new(ColorSpace::Srgb);
+    for gray in [16u8, 64, 128, 200] {
+        let image = RgbaImage::from_pixel(64, 64, Rgba([gray, gray, gray, 255]));
+        for size in [4, 6, 8] {
+            let thresholds = ThresholdMap::bayer(size, 255.0, false).unwrap();
+            let indices = ordered_quantize(&palette, &[black, white], &image, &thresholds, None, &distance, false);
+            let share = indices.iter().filter(|&&index| index == 1).count() as f32 / indices.len() as f32;
+            assert!((share - f32::from(gray) / 255.0).abs() < 1.0 / (size * size) as f32, "gray {gray}, bayer {size}: {share}");
+        }
+    }
+}
+
+#[test]
+fn invalid_bayer_sizes_are_rejected() {
+    let image = RgbaImage::from_pixel(4, 4, Rgba([9, 9, 9, 255]));
+    let result = Quantizer::new().dither(DitherMode::Bayer(5)).quantize(&image);
+    assert!(matches!(result, Err(QuantizeError::InvalidBayerSize(5))));
+    let result = Quantizer::new().dither(DitherMode::Bayer(4)).ordered_spread(-1.0).quantize(&image);
+    assert!(matches!(result, Err(QuantizeError::InvalidSpread(_))));
+}
+
+#[test]
+fn blue_noise_masks_hold_every_level_once() {
+    for size in [2, 3, 8, 17] {
+        let mask = void_and_cluster(size);
+        let levels: HashSet<u32> = mask.iter().copied().collect();
+        assert_eq!(levels, (0..(size * size) as u32).collect());
+    }
+    assert_eq!(void_and_cluster(16), void_and_cluster(16));
+}
+
+#[test]
+fn blue_noise_thresholds_spread_evenly() {
+    let size = 32;
+    let mask = void_and_cluster(size);
+    // at every level, each 4x4 block holds close to its share of the pixels below it.
+    for level in [64, 256, 512, 768, 960] {
+        for block_y in 0..size / 4 {
+            for block_x in 0..size / 4 {
+                let below = (0..16)
+                    .filter(|i| mask[(block_y * 4 + i / 4) * size + block_x * 4 + i % 4] < level)
+                    .count();
+                let expected = level as f32 / 64.0;
+                assert!((below as f32 - expected).abs() <= 2.0, "level {level}, block ({block_x}, {block_y}): {below}");
+            }
+        }
+    }
+}
+
+#[test]
+fn threshold_masks_tile_over_the_image() {
+    let black = Rgba([0, 0, 0, 255]);
+    let white = Rgba([255, 255, 255, 255]);
+    let palette = FixedPalette::new(vec![black, white], false);
+    let distance = WeightedRgb::new(ColorSpace::Srgb);
+    let mask = GrayImage::from_fn(2, 1, |x, _| Luma([if x == 0 { 0 } else { 255 }]));
+    let image = RgbaImage::from_pixel(5, 2, Rgba([128, 128, 128, 255]));
+    let thresholds = ThresholdMap::from_mask(&mask, 255.0, false);
+    let indices = ordered_quantize(&palette, &[black, white], &image, &thresholds, None, &distance, false);
+    assert_eq!(indices, [0, 1, 0, 1, 0, 0, 1, 0, 1, 0]);
+
+    let quantized = Quantizer::new()
+        .dither(DitherMode::BlueNoise)
+        .threshold_mask(GrayImage::new(0, 0))
+        .quantize(&image);
+    assert!(matches!(quantized, Err(QuantizeError::EmptyThresholdMask)));
+}
@@ -0,0 +1,97 @@
+use image::{GenericImageView, Rgba};
+use imgquant::core::fixed_palette::FixedPalette;
+use imgquant::core::rgb_helpers::WeightedRgb;
+use imgquant::dither::pattern::{mixing_plan, pattern_quantize, MixingMethod, PatternDither};
+use imgquant::{ColorSpace, DitherMode};
+
+mod common;
+
+use common::{assert_flat_grays_keep_their_brightness, assert_transparent_pixels_keep_their_slot, noise};
+
+const METHODS: [MixingMethod; 2] = [MixingMethod::Knoll, MixingMethod::Yliluoma];
+
+#[test]
+fn plans_mix_to_the_color() {
+    let palette = [
+        Rgba([0, 0, 0, 255]),
+        Rgba([255, 255, 255, 255]),
+        Rgba([200, 30, 30, 255]),
+        Rgba([20, 40, 180, 255]),
+        Rgba([240, 220, 60, 255]),
+    ];
+    let distance = WeightedRgb::new(ColorSpace::Srgb);
+    for method in METHODS {
+        for color in [Rgba([128, 128, 128, 255]), Rgba([110, 35, 105, 255]), Rgba([230, 120, 50, 255]), Rgba([70, 60, 150, 255])] {
+            let plan = mixing_plan(&PatternDither::new(method), &palette, &color, &distance);
+            assert_eq!(plan.len(), 64);
+            let mean = [0, 1, 2].map(|c| plan.iter().map(|&index| f32::from(palette[index as usize].0[c])).sum::<f32>() / 64.0);
+            for c in 0..3 {
+                assert!((mean[c] - f32::from(color.0[c])).abs() < 12.0, "{method:?}, {color:?}: {mean:?}");
+            }
+        }
+    }
+}
+
+#[test]
+fn palette_colors_are_not_mixed() {
+    let palette = [Rgba([0, 0, 0, 255]), Rgba([90, 160, 30, 255]), Rgba([255, 255, 255, 255])];
+    let distance = WeightedRgb::new(ColorSpace::Srgb);
+    for method in METHODS {
+        assert!(mixing_plan(&PatternDither::new(method), &palette, &palette[1], &distance).iter().all(|&index| index == 1), "{method:?}");
+    }
+}
+
+#[test]
+fn flat_grays_keep_their_brightness() {
+    let distance = WeightedRgb::new(ColorSpace::Srgb);
+    for method in METHODS {
+        // every 8x8 tile holds a whole plan, so the share is exact up to the plan's 64 entries.
+        assert_flat_grays_keep_their_brightness(&[20, 64, 128, 190, 240], 1.0 / 64.0, &format!("{method:?}"), |generator, palette, image| {
+            pattern_quantize(generator, palette, image, &PatternDither::new(method), None, &distance)
+        });
+    }
+}
+
+#[test]
+fn transparent_pixels_keep_their_slot() {
+    for dither_mode in [DitherMode::Knoll, DitherMode::Yliluoma] {
+        assert_transparent_pixels_keep_their_slot(dither_mode);
+    }
+}
+
+#[test]
+fn plans_are_ordered_by_srgb_luma_in_other_spaces() {
+    let (blue, green) = (Rgba([0, 0, 255, 255]), Rgba([0, 255, 0, 255]));
+    let luma = |rgba: Rgba<u8>| 299 * u32::from(rgba.0[0]) + 587 * u32::from(rgba.0[1]) + 114 * u32::from(rgba.0[2]);
+    for color_space in [ColorSpace::Lab, ColorSpace::Oklab, ColorSpace::Yuv] {
+        let palette = [color_space.encode(green), color_space.encode(blue)];
+        let distance = WeightedRgb::new(color_space);
+        for method in METHODS {
+            let dither = PatternDither::new(method).color_space(color_space);
+            let plan = mixing_plan(&dither, &palette, &color_space.encode(Rgba([0, 128, 128, 255])), &distance);
+            assert!(plan.contains(&0) && plan.contains(&1), "{color_space:?}, {method:?}");
+            // blue is darker on screen, whatever its bytes say in this space.
+            assert!(plan.windows(2).all(|pair| luma(color_space.decode(palette[pair[0] as usize])) <= luma(color_space.decode(palette[pair[1] as usize]))));
+            assert_eq!(plan[0], 1, "{color_space:?}, {method:?}");
+        }
+    }
+}
+
+#[test]
+fn pixels_only_depend_on_their_color_and_position() {
+    let palette: Vec<Rgba<u8>> = (0..12u32).map(|i| Rgba([(i * 21) as u8, (255 - i * 19) as u8, (i * 71 % 256) as u8, 255])).collect();
+    let generator = FixedPalette::new(palette.clone(), false);
+    let distance = WeightedRgb::new(ColorSpace::Srgb);
+    let image = noise(1618, 40, 32, false);
+    // the crop starts on a matrix boundary, so every pixel keeps its matrix cell.
+    let crop = image.view(8, 16, 24, 16).to_image();
+    for method in METHODS {
+        let dither = PatternDither::new(method);
+        let whole = pattern_quantize(&generator, &palette, &image, &dither, None, &distance);
+        let cropped = pattern_quantize(&generator, &palette, &crop, &dither, None, &distance);
+        for (i, &index) in cropped.iter().enumerate() {
+            let (x, y) = (8 + i % 24, 16 + i / 24);
+            assert_eq!(index, whole[y * 40 + x], "{method:?} at {x}, {y}");
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use image::{Rgba, RgbaImage};
+use imgquant::{DitherMode, QuantizeError, Quantizer};
+
+const COLORS: [Rgba<u8>; 4] = [
+    Rgba([200, 30, 30, 255]),
+    Rgba([20, 160, 60, 255]),
+    Rgba([40, 60, 220, 255]),
+    Rgba([240, 230, 200, 255]),
+];
+
+/// Flat 4x4 blocks of the four colors.
+fn flat_blocks(width: u32, height: u32) -> RgbaImage {
+    RgbaImage::from_fn(width, height, |x, y| COLORS[((x / 4 + y / 4) % 4) as usize])
+}
+
+#[test]
+fn invalid_settings_are_rejected() {
+    let image = flat_blocks(8, 8);
+    assert!(matches!(Quantizer::new().colors(1).quantize(&image), Err(QuantizeError::InvalidColorCount(1))));
+    assert!(matches!(Quantizer::new().depth(2).quantize(&image), Err(QuantizeError::InvalidDepth(2))));
+    assert!(matches!(Quantizer::new().depth(11).quantize(&image), Err(QuantizeError::InvalidDepth(11))));
+}
+
+#[test]
+fn indices_cover_every_pixel_and_stay_in_palette() {
+    let image = RgbaImage::from_fn(37, 23, |x, y| Rgba([(x * 7) as u8, (y * 11) as u8, ((x + y) * 5) as u8, 255]));
+    for colors in [2, 16, 256] {
+        for dither in [DitherMode::Base, DitherMode::FloydSteinberg, DitherMode::SierraLite] {
+            let quantized = Quantizer::new().colors(colors).dither(dither).quantize(&image).unwrap();
+            assert_eq!((quantized.width, quantized.height), (37, 23));
+            assert_eq!(quantized.indices.len(), 37 * 23);
+            assert!(quantized.palette.len() <= colors as usize);
+            assert!(quantized.indices.iter().all(|&index| (index as usize) < quantized.palette.len()));
+        }
+    }
+}
+
+#[test]
+fn flat_colors_come_back_unchanged() {
+    let image = flat_blocks(16, 16);
+    let quantized = Quantizer::new().colors(16).dither(DitherMode::Base).quantize(&image).unwrap();
+    assert_eq!(quantized.palette.len(), COLORS.len());
+    assert_eq!(quantized.to_rgba_image(), image);
+}
+
+#[test]
+fn source_alpha_is_carried_through() {
+    let opaque = flat_blocks(8, 8);
+    assert!(Quantizer::new().quantize(&opaque).unwrap().alpha.is_none());
+
+    let translucent = RgbaImage::from_fn(8, 8, |x, y| {
+        let [r, g, b, _] = opaque.get_pixel(x, y).0;
+        Rgba([r, g, b, (x * 32) as u8])
+    });
+    let quantized = Quantizer::new().dither(DitherMode::Base).quantize(&translucent).unwrap();
+    let alpha: Vec<u8> = translucent.pixels().map(|rgba| rgba.0[3]).collect();
+    assert_eq!(quantized.alpha.as_ref(), Some(&alpha));
+    assert_eq!(quantized.to_rgba_image(), translucent);
+}
+
+#[test]
+fn alpha_is_quantized_into_the_palette() {
+    let translucent = RgbaImage::from_fn(16, 16, |x, y| {
+        let [r, g, b, _] = COLORS[((x / 4 + y / 4) % 4) as usize].0;
+        Rgba([r, g, b, [255, 160, 40][(x / 8 + y / 8) as usize % 3]])
+    });
+    let quantized = Quantizer::new().alpha(true).dither(DitherMode::Base).quantize(&translucent).unwrap();
+    assert!(quantized.alpha.is_none());
+    assert!(quantized.palette.iter().any(|rgba| rgba.0[3] < 255));
+    assert_eq!(quantized.to_rgba_image(), translucent);
+
+    // without it the palette stays opaque and the alpha rides along per pixel.
+    let quantized = Quantizer::new().dither(DitherMode::Base).quantize(&translucent).unwrap();
+    assert!(quantized.palette.iter().all(|rgba| rgba.0[3] == 255));
+    assert!(quantized.alpha.is_some());
+    assert_eq!(quantized.to_rgba_image(), translucent);
+}
+
+#[test]
+fn transparent_pixels_get_the_last_slot() {
+    // fully transparent pixels with leftover color, which must not take up entries.
+    let image = RgbaImage::from_fn(16, 16, |x, y| {
+        if (x + y) % 3 == 0 {
+            Rgba([(x * 16) as u8, (y * 16) as u8, 99, 0])
+        } else {
+            COLORS[((x / 4 + y / 4) % 4) as usize]
+        }
+    });
+    for with_alpha in [false, true] {
+        for dither in [DitherMode::Base, DitherMode::FloydSteinberg] {
+            let quantized = Quantizer::new()
+                .colors(5)
+                .alpha(with_alpha)
+                .transparent_slot(true)
+                .dither(dither.clone())
+                .quantize(&image)
+                .unwrap();
+            assert_eq!(quantized.palette.len(), 5);
+            let slot = quantized.palette.len() as u32 - 1;
+            assert_eq!(quantized.palette[slot as usize].0[3], 0);
+            for (rgba, &index) in image.pixels().zip(&quantized.indices) {
+                assert_eq!(index == slot, rgba.0[3] == 0, "{rgba:?} with {dither:?}");
+            }
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use imgquant::core::rgb_helpers::WeightedRgb;
+use imgquant::dither::riemersma::{hilbert_curve, riemersma_quantize};
+use imgquant::dither::DiffusionControls;
+use imgquant::{ColorSpace, DitherMode};
+
+mod common;
+
+use common::{assert_flat_grays_keep_their_brightness, assert_transparent_pixels_keep_their_slot};
+
+#[test]
+fn hilbert_curve_visits_every_pixel_once_in_small_steps() {
+    for (width, height) in [(1, 1), (1, 7), (7, 1), (2, 2), (8, 8), (5, 3), (3, 5), (16, 9), (31, 17), (64, 10), (100, 37)] {
+        let mut visited = vec![false; (width * height) as usize];
+        let mut steps = Vec::new();
+        let mut diagonal_steps = 0;
+        hilbert_curve(width, height, |x, y| {
+            assert!(x < width && y < height, "{width}x{height}: ({x}, {y})");
+            let index = (y * width + x) as usize;
+            assert!(!visited[index], "{width}x{height}: ({x}, {y}) visited twice");
+            visited[index] = true;
+            steps.push((i64::from(x), i64::from(y)));
+        });
+        assert!(visited.iter().all(|&visited| visited), "{width}x{height}");
+        for pair in steps.windows(2) {
+            let (dx, dy) = ((pair[1].0 - pair[0].0).abs(), (pair[1].1 - pair[0].1).abs());
+            assert!(dx <= 1 && dy <= 1, "{width}x{height}: {pair:?}");
+            if dx + dy == 2 {
+                diagonal_steps += 1;
+            }
+        }
+        if width.is_power_of_two() && width == height {
+            assert_eq!(diagonal_steps, 0, "{width}x{height}");
+        }
+    }
+}
+
+#[test]
+fn flat_grays_keep_their_brightness() {
+    let distance = WeightedRgb::new(ColorSpace::Srgb);
+    assert_flat_grays_keep_their_brightness(&[20, 64, 128, 190, 240], 0.02, "riemersma", |generator, palette, image| {
+        riemersma_quantize(generator, palette, image, None, &distance, &DiffusionControls::new())
+    });
+}
+
+#[test]
+fn transparent_pixels_keep_their_slot() {
+    assert_transparent_pixels_keep_their_slot(DitherMode::Riemersma);
+}
@@ -0,0 +1,57 @@
+use std::collections::HashSet;
+
+use image::{Rgba, RgbaImage};
+use imgquant::core::palette_generator::PaletteGenerator;
+use imgquant::core::wu::WuQuantizer;
+
+mod common;
+
+use common::{add_image, assert_exact_palette, assert_few_colors_map_to_themselves, noise, synthetic_images};
+
+/// The 32x32x32 histogram cells `image` falls into, which is as many colors as Wu can tell apart.
+fn cell_count(image: &RgbaImage) -> usize {
+    image.pixels().map(|rgba| [rgba.0[0] >> 3, rgba.0[1] >> 3, rgba.0[2] >> 3]).collect::<HashSet<_>>().len()
+}
+
+#[test]
+fn palette_is_exactly_min_of_count_and_cells() {
+    for image in synthetic_images() {
+        for color_count in [1, 2, 3, 7, 16, 64, 256, 1000] {
+            let quantizer = add_image(WuQuantizer::new(), &image);
+            assert_eq!(quantizer.color_count(), cell_count(&image));
+            assert_exact_palette(quantizer, &image, color_count, cell_count(&image));
+        }
+    }
+}
+
+#[test]
+fn few_colors_map_to_themselves() {
+    assert_few_colors_map_to_themselves(WuQuantizer::new, 0);
+}
+
+#[test]
+fn cuts_go_where_the_variance_drops_most() {
+    // halving the pixels would cut between 0 and 64, but a lone 255 costs far more squared error.
+    let gray = |value| Rgba([value, value, value, 255]);
+    let image = RgbaImage::from_fn(201, 1, |x, _| match x {
+        0..100 => gray(0),
+        100..200 => gray(64),
+        _ => gray(255),
+    });
+    let mut quantizer = add_image(WuQuantizer::new(), &image);
+    let mut palette = quantizer.make_palette(2);
+    palette.sort_by_key(|rgba| rgba.0);
+    // (100 * 64 + 255) / 101
+    assert_eq!(palette, [gray(0), gray(65)]);
+}
+
+#[test]
+fn alpha_is_left_out() {
+    let image = noise(4099, 24, 24, true);
+    let mut quantizer = add_image(WuQuantizer::new(), &image);
+    let palette = quantizer.make_palette(16);
+    assert!(!quantizer.has_alpha());
+    assert!(palette.iter().all(|rgba| rgba.0[3] == u8::MAX));
+    // every color is found, whatever its alpha, and so is one that was never added.
+    assert!(quantizer.get_palette_index(Rgba([1, 2, 3, 0]), false).is_some());
+}